@@ -0,0 +1,57 @@
+use crate::error::Error;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The IDs of messages that have already been replayed, kept in a file with one ID per line so
+/// an interrupted replay can pick up where it left off.
+pub(crate) struct Checkpoint {
+    path: String,
+    replayed: HashSet<String>,
+    file: BufWriter<File>,
+}
+
+impl Checkpoint {
+    /// Reads the IDs already in the file, creating it if it doesn't exist yet.
+    pub(crate) fn open(path: &Path) -> Result<Checkpoint, Error> {
+        let display = path.display().to_string();
+        let io_error = |error| Error::Io(display.clone(), error);
+        let replayed = match File::open(path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| line.map(|line| line.trim().to_string()))
+                .collect::<Result<HashSet<String>, io::Error>>()
+                .map_err(io_error)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(error) => return Err(io_error(error)),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+
+        Ok(Checkpoint {
+            path: display,
+            replayed,
+            file: BufWriter::new(file),
+        })
+    }
+
+    pub(crate) fn contains(&self, message_id: &str) -> bool {
+        self.replayed.contains(message_id)
+    }
+
+    /// Adds the IDs to the file, flushing it straight away so a crash loses as little as possible.
+    pub(crate) fn record(&mut self, message_ids: Vec<String>) -> Result<(), Error> {
+        let path = &self.path;
+        let io_error = |error| Error::Io(path.clone(), error);
+        for message_id in message_ids {
+            writeln!(self.file, "{}", message_id).map_err(io_error)?;
+            self.replayed.insert(message_id);
+        }
+        self.file.flush().map_err(io_error)
+    }
+}
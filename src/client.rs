@@ -0,0 +1,138 @@
+use crate::compat::Future01Ext;
+use crate::error::Error;
+use crate::sts::{AssumeRole, AssumeRoleProvider, WebIdentityProvider};
+use futures::Future;
+use rusoto_core::credential::{
+    AutoRefreshingProvider, AwsCredentials, CredentialsError, DefaultCredentialsProvider,
+    EnvironmentProvider, ProfileProvider, ProvideAwsCredentials,
+};
+use rusoto_core::{Client, HttpClient, Region};
+use rusoto_sqs::SqsClient;
+
+/// Where the credentials for talking to SQS, or for assuming a role first, come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CredentialSource {
+    /// A named profile if there is one, then web identity if the environment is set up for it,
+    /// and otherwise rusoto's chain of environment variables, the default profile, the ECS
+    /// container role and the EC2 instance role
+    #[default]
+    Chain,
+    /// A profile in `~/.aws/credentials`. AWS SSO sessions can be used through a profile with
+    /// `credential_process = aws configure export-credentials --profile <sso-profile> --format process`.
+    Profile,
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`
+    Environment,
+    /// The token in `AWS_WEB_IDENTITY_TOKEN_FILE` exchanged for `AWS_ROLE_ARN`'s credentials,
+    /// as set up for EKS service accounts
+    WebIdentity,
+}
+
+/// How the client authenticates.
+#[derive(Clone, Debug, Default)]
+pub struct CredentialOptions {
+    pub source: CredentialSource,
+    /// The profile to use with `CredentialSource::Profile` or `CredentialSource::Chain`
+    pub profile: Option<String>,
+    /// A role to assume with the credentials from `source`, so the client uses the role's
+    /// temporary credentials instead, assuming it again before they expire
+    pub assume_role: Option<AssumeRole>,
+}
+
+// Whichever provider was chosen, as one type the clients can be built with
+enum Provider {
+    Chain(Box<DefaultCredentialsProvider>),
+    Profile(ProfileProvider),
+    Environment(EnvironmentProvider),
+    WebIdentity(AutoRefreshingProvider<WebIdentityProvider>),
+}
+
+impl ProvideAwsCredentials for Provider {
+    type Future = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+    fn credentials(&self) -> Self::Future {
+        match self {
+            Provider::Chain(provider) => Box::new(provider.credentials()),
+            Provider::Profile(provider) => Box::new(provider.credentials()),
+            Provider::Environment(provider) => Box::new(provider.credentials()),
+            Provider::WebIdentity(provider) => Box::new(provider.credentials()),
+        }
+    }
+}
+
+/// Builds an SQS client for `region` with credentials from wherever `options` says.
+pub async fn sqs_client(region: Region, options: &CredentialOptions) -> Result<SqsClient, Error> {
+    let source = match options.source {
+        CredentialSource::Chain if options.profile.is_some() => CredentialSource::Profile,
+        CredentialSource::Chain if WebIdentityProvider::is_configured() => {
+            CredentialSource::WebIdentity
+        }
+        source => source,
+    };
+    let profile = options.profile.as_deref().unwrap_or("default");
+    let source_error = |error| match source {
+        CredentialSource::Profile => Error::Profile(profile.to_string(), error),
+        CredentialSource::Environment => Error::Credentials("environment variables", error),
+        CredentialSource::WebIdentity => Error::Credentials("web identity", error),
+        CredentialSource::Chain => Error::Credentials("the default credential chain", error),
+    };
+
+    let provider = match source {
+        CredentialSource::Chain => Provider::Chain(Box::new(
+            DefaultCredentialsProvider::new().map_err(source_error)?,
+        )),
+        CredentialSource::Profile => {
+            let mut provider = ProfileProvider::new().map_err(source_error)?;
+            if let Some(profile) = &options.profile {
+                provider.set_profile(profile.as_str());
+            }
+            Provider::Profile(provider)
+        }
+        CredentialSource::Environment => Provider::Environment(EnvironmentProvider::default()),
+        CredentialSource::WebIdentity => {
+            let sts_client = Client::new_not_signing(HttpClient::new().map_err(Error::HttpClient)?);
+            let provider =
+                WebIdentityProvider::from_env(sts_client, region.clone()).ok_or_else(|| {
+                    source_error(CredentialsError::new(
+                        "AWS_WEB_IDENTITY_TOKEN_FILE and AWS_ROLE_ARN must both be set",
+                    ))
+                })?;
+            Provider::WebIdentity(AutoRefreshingProvider::new(provider).map_err(source_error)?)
+        }
+    };
+    // Rusoto only loads credentials when the first request is signed, so check ones that were
+    // asked for up front rather than failing part way through. The chain is left to find
+    // credentials when they're needed, as there's nothing specific to blame if it can't.
+    if source != CredentialSource::Chain {
+        provider
+            .credentials()
+            .compat()
+            .await
+            .map_err(source_error)?;
+    }
+
+    let role = match &options.assume_role {
+        Some(role) => role,
+        None => {
+            let http_client = HttpClient::new().map_err(Error::HttpClient)?;
+            return Ok(SqsClient::new_with(http_client, provider, region));
+        }
+    };
+
+    let assume_role_error = |error| Error::AssumeRole(role.role_arn.clone(), error);
+    let sts_client = Client::new_with(provider, HttpClient::new().map_err(Error::HttpClient)?);
+    let provider = AutoRefreshingProvider::new(AssumeRoleProvider::new(
+        sts_client,
+        region.clone(),
+        role.clone(),
+    ))
+    .map_err(assume_role_error)?;
+    // As with the other credentials, fail now if the role can't be assumed
+    provider
+        .credentials()
+        .compat()
+        .await
+        .map_err(assume_role_error)?;
+
+    let http_client = HttpClient::new().map_err(Error::HttpClient)?;
+    Ok(SqsClient::new_with(http_client, provider, region))
+}
@@ -0,0 +1,60 @@
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::sync::oneshot::{self, SpawnHandle};
+use futures::Async;
+use lazy_static::lazy_static;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+lazy_static! {
+    // Rusoto's HTTP client is built on tokio 0.1 so its requests need a tokio 0.1 runtime to
+    // drive them, separate from the tokio 0.2 runtime the rest of the tool runs on
+    static ref RUSOTO_RUNTIME: tokio01::runtime::Runtime =
+        tokio01::runtime::Runtime::new().expect("Failed to start runtime for SQS requests");
+}
+
+/// Converts a futures 0.1 future, like a rusoto request, into a `std::future::Future` so it can
+/// be `.await`ed.
+pub trait Future01Ext: futures::Future {
+    fn compat(self) -> Compat<Self::Item, Self::Error>;
+}
+
+impl<F> Future01Ext for F
+where
+    F: futures::Future + Send + 'static,
+    F::Item: Send + 'static,
+    F::Error: Send + 'static,
+{
+    fn compat(self) -> Compat<F::Item, F::Error> {
+        Compat {
+            inner: executor::spawn(oneshot::spawn(self, &RUSOTO_RUNTIME.executor())),
+        }
+    }
+}
+
+/// A future running on the rusoto runtime. Dropping it cancels the future.
+pub struct Compat<T, E> {
+    inner: Spawn<SpawnHandle<T, E>>,
+}
+
+impl<T, E> Future for Compat<T, E> {
+    type Output = Result<T, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let notify = NotifyHandle::from(Arc::new(WakerNotify(cx.waker().clone())));
+        match self.inner.poll_future_notify(&notify, 0) {
+            Ok(Async::Ready(value)) => Poll::Ready(Ok(value)),
+            Ok(Async::NotReady) => Poll::Pending,
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+}
+
+struct WakerNotify(Waker);
+
+impl Notify for WakerNotify {
+    fn notify(&self, _id: usize) {
+        self.0.wake_by_ref();
+    }
+}
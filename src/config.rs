@@ -0,0 +1,162 @@
+use crate::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Looked for in the home directory when --config isn't given
+const DEFAULT_CONFIG_FILE: &str = ".sqs-replay.toml";
+
+/// Defaults for command line arguments, read from a config file. Values are kept as the strings
+/// they'd be given as on the command line so they're validated the same way.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub batch_size: Option<String>,
+    pub wait_time: Option<String>,
+    pub visibility_timeout: Option<String>,
+}
+
+/// Reads the config file at `path`, or `~/.sqs-replay.toml` if there is one. A missing default
+/// config file means there are no defaults, but a missing `path` is an error.
+pub fn load_config(path: Option<&Path>) -> Result<Config, Error> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match dirs::home_dir() {
+            Some(home) => (home.join(DEFAULT_CONFIG_FILE), false),
+            None => return Ok(Config::default()),
+        },
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse_config(&path, &contents),
+        Err(error) if error.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+        Err(error) => Err(Error::Io(path.display().to_string(), error)),
+    }
+}
+
+// Only the flat `key = value` subset of TOML the config needs is supported: basic strings with no
+// escapes, integers and `#` comments, with no tables or arrays. The `toml` crate would cover the
+// rest, but it isn't one of the crate's dependencies.
+fn parse_config(path: &Path, contents: &str) -> Result<Config, Error> {
+    let malformed =
+        |line: usize, reason: &str| Error::Config(path.display().to_string(), line, reason.into());
+    let mut config = Config::default();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(malformed(i + 1, "tables aren't supported"));
+        }
+        let (key, value) = match line.find('=') {
+            Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+            None => return Err(malformed(i + 1, "expected key = value")),
+        };
+        let value = parse_value(value).ok_or_else(|| malformed(i + 1, "invalid value"))?;
+        let (field, expected) = match key {
+            "region" => (&mut config.region, ValueType::String),
+            "profile" => (&mut config.profile, ValueType::String),
+            "batch_size" => (&mut config.batch_size, ValueType::Integer),
+            "wait_time" => (&mut config.wait_time, ValueType::Integer),
+            "visibility_timeout" => (&mut config.visibility_timeout, ValueType::Integer),
+            _ => return Err(malformed(i + 1, &format!("unknown key {}", key))),
+        };
+        if value.0 != expected {
+            let reason = match expected {
+                ValueType::String => format!("{} must be a quoted string", key),
+                ValueType::Integer => format!("{} must be an integer", key),
+            };
+            return Err(malformed(i + 1, &reason));
+        }
+        if field.is_some() {
+            return Err(malformed(i + 1, &format!("{} is set more than once", key)));
+        }
+        *field = Some(value.1);
+    }
+    Ok(config)
+}
+
+#[derive(Debug, PartialEq)]
+enum ValueType {
+    String,
+    Integer,
+}
+
+// A value's type and the string it would be given as on the command line
+fn parse_value(value: &str) -> Option<(ValueType, String)> {
+    let value = match value.find(" #") {
+        Some(comment) if !value.starts_with('"') => value[..comment].trim_end(),
+        _ => value,
+    };
+    if let Some(quoted) = value.strip_prefix('"') {
+        let end = quoted.find('"')?;
+        let rest = quoted[end + 1..].trim_start();
+        if quoted[..end].contains('\\') || !(rest.is_empty() || rest.starts_with('#')) {
+            return None;
+        }
+        Some((ValueType::String, quoted[..end].to_string()))
+    } else if value.parse::<i64>().is_ok() {
+        Some((ValueType::Integer, value.to_string()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config, Error> {
+        parse_config(Path::new("config.toml"), contents)
+    }
+
+    #[test]
+    fn reads_defaults() {
+        let config = parse(
+            "# Defaults for the dev account\n\
+             region = \"eu-west-2\"\n\
+             profile = \"dev\" # not the default profile\n\
+             \n\
+             batch_size = 5\n\
+             visibility_timeout = 60 # seconds\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                region: Some(String::from("eu-west-2")),
+                profile: Some(String::from("dev")),
+                batch_size: Some(String::from("5")),
+                wait_time: None,
+                visibility_timeout: Some(String::from("60")),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_values() {
+        assert!(matches!(
+            parse("regoin = \"eu-west-2\""),
+            Err(Error::Config(_, 1, _))
+        ));
+        assert!(matches!(
+            parse("\nregion = eu-west-2"),
+            Err(Error::Config(_, 2, _))
+        ));
+        assert!(matches!(parse("[send]"), Err(Error::Config(_, 1, _))));
+        assert!(matches!(
+            parse("batch_size = \"5\""),
+            Err(Error::Config(_, 1, _))
+        ));
+        assert!(matches!(
+            parse("region = \"eu-west-1\"\nregion = \"eu-west-2\""),
+            Err(Error::Config(_, 2, _))
+        ));
+        assert!(matches!(
+            parse("profile = \"dev\\\\test\""),
+            Err(Error::Config(_, 1, _))
+        ));
+    }
+}
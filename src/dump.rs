@@ -0,0 +1,173 @@
+use crate::error::Error;
+use crate::output::{Event, Output};
+use crate::replay::{delete_message_batch, DEFAULT_EMPTY_RECEIVES, MAX_BATCH_SIZE};
+use crate::retry::RetryPolicy;
+use rusoto_sqs::{Message, MessageAttributeValue, ReceiveMessageRequest, Sqs};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Long enough that the whole queue can be read before the first messages become visible again
+const DUMP_VISIBILITY_TIMEOUT: i64 = 30;
+
+const DUMP_WAIT_TIME_SECONDS: i64 = 3;
+
+/// Where to dump messages from and to.
+pub struct DumpOptions {
+    pub queue_url: String,
+    /// File to write messages to, one JSON object per line. It's replaced if it already exists.
+    pub output_file: PathBuf,
+    /// Delete messages from the queue once they've been written
+    pub delete: bool,
+    pub retry_policy: RetryPolicy,
+}
+
+/// Writes every message in the queue to a file, returning the number of messages written.
+pub async fn dump(client: &dyn Sqs, options: DumpOptions, output: &Output) -> Result<usize, Error> {
+    let queue_url = options.queue_url.as_str();
+    let path = options.output_file.display().to_string();
+    let io_error = |error| Error::Io(path.clone(), error);
+    let mut file = BufWriter::new(File::create(&options.output_file).map_err(io_error)?);
+
+    let mut batch_no = 1;
+    let mut dumped = 0;
+    let mut empty_receives = 0;
+    // Deletes that fail don't stop the dump, but still need reporting once it's finished
+    let mut undeleted = 0;
+    // Messages that are left in the queue come back around once their visibility timeout expires
+    let mut seen_message_ids = HashSet::new();
+
+    loop {
+        output.emit(Event::BatchRequested {
+            batch: batch_no,
+            count: MAX_BATCH_SIZE,
+        });
+        let receive_message_input = ReceiveMessageRequest {
+            queue_url: String::from(queue_url),
+            max_number_of_messages: Some(MAX_BATCH_SIZE),
+            wait_time_seconds: Some(DUMP_WAIT_TIME_SECONDS),
+            visibility_timeout: Some(DUMP_VISIBILITY_TIMEOUT),
+            attribute_names: Some(vec![String::from("All")]),
+            message_attribute_names: Some(vec![String::from("All")]),
+            ..Default::default()
+        };
+
+        let result = options
+            .retry_policy
+            .allowing(Duration::from_secs(DUMP_WAIT_TIME_SECONDS as u64))
+            .run(output, || {
+                client.receive_message(receive_message_input.clone())
+            })
+            .await
+            .map_err(|error| Error::ReceiveMessage(String::from(queue_url), Box::new(error)))?;
+        let messages = result.messages.unwrap_or_default();
+        if messages.is_empty() {
+            empty_receives += 1;
+            output.emit(Event::BatchEmpty { batch: batch_no });
+            if empty_receives >= DEFAULT_EMPTY_RECEIVES {
+                break;
+            }
+            batch_no += 1;
+            continue;
+        }
+        output.emit(Event::BatchReceived {
+            batch: batch_no,
+            count: messages.len(),
+        });
+
+        let new_messages: Vec<&Message> = messages
+            .iter()
+            .filter(|m| match &m.message_id {
+                Some(message_id) => seen_message_ids.insert(message_id.clone()),
+                None => false,
+            })
+            .collect();
+        // There may still be messages that haven't been received yet behind ones whose visibility
+        // timeout has expired, so only receiving nothing new several times in a row ends the dump
+        if new_messages.is_empty() {
+            empty_receives += 1;
+            output.emit(Event::BatchAlreadySeen { batch: batch_no });
+            if empty_receives >= DEFAULT_EMPTY_RECEIVES {
+                break;
+            }
+            batch_no += 1;
+            continue;
+        }
+        empty_receives = 0;
+
+        for m in new_messages.iter() {
+            writeln!(file, "{}", message_to_json(m)).map_err(io_error)?;
+        }
+        // Messages are only deleted once they're safely on disk
+        file.flush().map_err(io_error)?;
+        if options.delete {
+            file.get_ref().sync_data().map_err(io_error)?;
+        }
+        dumped += new_messages.len();
+        output.emit(Event::MessagesDumped {
+            count: new_messages.len(),
+            output_file: &path,
+        });
+
+        if options.delete {
+            match delete_message_batch(
+                client,
+                queue_url,
+                &new_messages,
+                &options.retry_policy,
+                output,
+            )
+            .await
+            {
+                Ok(deleted) => undeleted += new_messages.len() - deleted.count,
+                Err(error) => {
+                    undeleted += new_messages.len();
+                    output.emit(Event::Error {
+                        message: &error.to_string(),
+                    });
+                }
+            }
+        }
+        batch_no += 1;
+    }
+
+    output.emit(Event::DumpFinished {
+        dumped,
+        output_file: &path,
+    });
+    if undeleted > 0 {
+        return Err(Error::MessagesNotDeleted(undeleted));
+    }
+    Ok(dumped)
+}
+
+pub(crate) fn message_to_json(message: &Message) -> Value {
+    json!({
+        "message_id": message.message_id,
+        "body": message.body,
+        "attributes": message.attributes,
+        "message_attributes": message
+            .message_attributes
+            .as_ref()
+            .map(message_attributes_to_json),
+    })
+}
+
+// Binary attribute values are base64 encoded, the same as in the SQS API
+fn message_attributes_to_json(attributes: &HashMap<String, MessageAttributeValue>) -> Value {
+    let mut map = Map::new();
+    for (name, value) in attributes {
+        map.insert(
+            name.clone(),
+            json!({
+                "data_type": value.data_type,
+                "string_value": value.string_value,
+                "binary_value": value.binary_value.as_ref().map(|b| base64::encode(&b[..])),
+            }),
+        );
+    }
+    Value::Object(map)
+}
@@ -0,0 +1,433 @@
+use rusoto_core::credential::CredentialsError;
+use rusoto_core::region::ParseRegionError;
+use rusoto_core::request::TlsError;
+use rusoto_core::RusotoError;
+use rusoto_sqs::{
+    CreateQueueError, DeleteMessageBatchError, GetQueueAttributesError, GetQueueUrlError,
+    ListDeadLetterSourceQueuesError, ListQueuesError, PurgeQueueError, ReceiveMessageError,
+    SendMessageBatchError,
+};
+use std::fmt;
+use std::io;
+use xml::reader::{EventReader, XmlEvent};
+
+// Rusoto's errors are boxed to keep the size of `Result<_, Error>` down
+#[derive(Debug)]
+pub enum Error {
+    MissingSubcommand,
+    InvalidRegion(String, ParseRegionError),
+    Profile(String, CredentialsError),
+    Credentials(&'static str, CredentialsError),
+    AssumeRole(String, CredentialsError),
+    HttpClient(TlsError),
+    MissingMessageGroupId,
+    DelayWithFifoDestination,
+    /// The queue, and whether it was required to be FIFO
+    WrongQueueType(String, bool),
+    AmbiguousRedriveDestination(usize),
+    SameSourceAndDestination,
+    NothingToReplay,
+    /// Why deleting replayed messages from the source would lose them
+    UnsafeDelete(&'static str),
+    ListQueues(Box<RusotoError<ListQueuesError>>),
+    PurgeQueue(String, Box<RusotoError<PurgeQueueError>>),
+    CreateQueue(String, Box<RusotoError<CreateQueueError>>),
+    GetQueueAttributes(String, Box<RusotoError<GetQueueAttributesError>>),
+    GetQueueUrl(String, Box<RusotoError<GetQueueUrlError>>),
+    ListDeadLetterSourceQueues(String, Box<RusotoError<ListDeadLetterSourceQueuesError>>),
+    ReceiveMessage(String, Box<RusotoError<ReceiveMessageError>>),
+    SendMessageBatch(String, Box<RusotoError<SendMessageBatchError>>),
+    DeleteMessageBatch(String, Box<RusotoError<DeleteMessageBatchError>>),
+    MessagesFailed(usize, usize),
+    MessagesNotDeleted(usize),
+    /// How many messages from a dump file couldn't be sent, and how many were
+    MessagesNotLoaded(usize, usize),
+    Io(String, io::Error),
+    MalformedLine(String, usize, String),
+    Config(String, usize, String),
+}
+
+/// The process exit code for each kind of failure, so scripts can tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitCode {
+    /// Bad arguments, config or credentials. Clap exits with this too.
+    Usage = 1,
+    /// Listing, looking up or inspecting queues failed
+    Queue = 2,
+    Receive = 3,
+    Send = 4,
+    Delete = 5,
+    /// Some messages were replayed but others were left behind
+    PartialSuccess = 6,
+    /// Reading or writing a local file failed
+    Io = 7,
+    /// There were no messages to replay and that was asked to be a failure
+    NothingToReplay = 8,
+}
+
+impl Error {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::MissingSubcommand
+            | Error::InvalidRegion(..)
+            | Error::Profile(..)
+            | Error::Credentials(..)
+            | Error::AssumeRole(..)
+            | Error::HttpClient(_)
+            | Error::MissingMessageGroupId
+            | Error::DelayWithFifoDestination
+            | Error::WrongQueueType(..)
+            | Error::AmbiguousRedriveDestination(_)
+            | Error::SameSourceAndDestination
+            | Error::UnsafeDelete(_)
+            | Error::Config(..) => ExitCode::Usage,
+            Error::ListQueues(_)
+            | Error::PurgeQueue(..)
+            | Error::CreateQueue(..)
+            | Error::GetQueueAttributes(..)
+            | Error::GetQueueUrl(..)
+            | Error::ListDeadLetterSourceQueues(..) => ExitCode::Queue,
+            Error::ReceiveMessage(..) => ExitCode::Receive,
+            Error::SendMessageBatch(..)
+            | Error::MessagesFailed(_, 0)
+            | Error::MessagesNotLoaded(_, 0) => ExitCode::Send,
+            Error::DeleteMessageBatch(..) | Error::MessagesNotDeleted(_) => ExitCode::Delete,
+            Error::MessagesFailed(..) | Error::MessagesNotLoaded(..) => ExitCode::PartialSuccess,
+            Error::NothingToReplay => ExitCode::NothingToReplay,
+            Error::Io(..) | Error::MalformedLine(..) => ExitCode::Io,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingSubcommand => write!(f, "Missing required subcommand"),
+            Error::InvalidRegion(name, error) => write!(f, "Invalid region {}: {}", name, error),
+            Error::Profile(name, error) => {
+                write!(
+                    f,
+                    "Failed to load credentials for profile {}: {}",
+                    name, error
+                )
+            }
+            Error::Credentials(source, error) => {
+                write!(f, "Failed to load credentials from {}: {}", source, error)
+            }
+            Error::AssumeRole(role_arn, error) => {
+                write!(f, "Failed to assume role {}: {}", role_arn, error)
+            }
+            Error::HttpClient(error) => write!(f, "Failed to create HTTP client: {}", error),
+            Error::AmbiguousRedriveDestination(0) => write!(
+                f,
+                "No queues use this dead-letter queue, use --destination-queue-url to choose where to redrive to"
+            ),
+            Error::AmbiguousRedriveDestination(count) => write!(
+                f,
+                "{} queues use this dead-letter queue, use --destination-queue-url to choose where to redrive to",
+                count
+            ),
+            Error::SameSourceAndDestination => write!(
+                f,
+                "The source and destination queues are the same, use --allow-same-queue if that's intended"
+            ),
+            Error::NothingToReplay => write!(
+                f,
+                "The source queues have no messages available to replay"
+            ),
+            Error::UnsafeDelete(reason) => write!(
+                f,
+                "Not replaying, as {} and messages would be deleted from the source without being sent anywhere",
+                reason
+            ),
+            Error::DelayWithFifoDestination => write!(
+                f,
+                "FIFO queues don't support per-message delays, set a delivery delay on the destination queue instead"
+            ),
+            Error::WrongQueueType(queue_url, fifo) => write!(
+                f,
+                "{} is a {} queue, but a {} queue was required",
+                queue_url,
+                if *fifo { "standard" } else { "FIFO" },
+                if *fifo { "FIFO" } else { "standard" }
+            ),
+            Error::MissingMessageGroupId => write!(
+                f,
+                "A message group ID is required when sending to a FIFO queue"
+            ),
+            Error::ListQueues(error) => write!(f, "Failed to list queues: {}", Cause(error)),
+            Error::PurgeQueue(url, error) => {
+                write!(f, "Failed to purge {}: {}", url, Cause(error))
+            }
+            Error::CreateQueue(name, error) => {
+                write!(f, "Failed to create queue {}: {}", name, Cause(error))
+            }
+            Error::ListDeadLetterSourceQueues(url, error) => write!(
+                f,
+                "Failed to find the queues that use {} as a dead-letter queue: {}",
+                url,
+                Cause(error)
+            ),
+            Error::GetQueueAttributes(url, error) => {
+                write!(f, "Failed to get attributes of {}: {}", url, Cause(error))
+            }
+            Error::GetQueueUrl(name, error) => {
+                write!(f, "Failed to find queue {}: {}", name, Cause(error))
+            }
+            Error::ReceiveMessage(url, error) => {
+                write!(f, "Failed to receive messages from {}: {}", url, Cause(error))
+            }
+            Error::SendMessageBatch(url, error) => {
+                write!(f, "Failed to send messages to {}: {}", url, Cause(error))
+            }
+            Error::DeleteMessageBatch(url, error) => {
+                write!(f, "Failed to delete messages from {}: {}", url, Cause(error))
+            }
+            Error::MessagesNotDeleted(count) => write!(
+                f,
+                "{} messages were dumped but couldn't be deleted from the queue",
+                count
+            ),
+            Error::MessagesNotLoaded(count, _) => write!(
+                f,
+                "{} messages from the file couldn't be sent to the destination queue",
+                count
+            ),
+            Error::MessagesFailed(count, _) => write!(
+                f,
+                "{} messages failed to replay and were left in the source queue",
+                count
+            ),
+            Error::Io(path, error) => write!(f, "{}: {}", path, error),
+            Error::Config(path, line, reason) => {
+                write!(f, "Invalid config on line {} of {}: {}", line, path, reason)
+            }
+            Error::MalformedLine(path, line, reason) => {
+                write!(
+                    f,
+                    "Malformed message on line {} of {}: {}",
+                    line, path, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidRegion(_, error) => Some(error),
+            Error::Profile(_, error)
+            | Error::Credentials(_, error)
+            | Error::AssumeRole(_, error) => Some(error),
+            Error::HttpClient(error) => Some(error),
+            Error::ListQueues(error) => Some(&**error),
+            Error::PurgeQueue(_, error) => Some(&**error),
+            Error::CreateQueue(_, error) => Some(&**error),
+            Error::GetQueueAttributes(_, error) => Some(&**error),
+            Error::GetQueueUrl(_, error) => Some(&**error),
+            Error::ListDeadLetterSourceQueues(_, error) => Some(&**error),
+            Error::ReceiveMessage(_, error) => Some(&**error),
+            Error::SendMessageBatch(_, error) => Some(&**error),
+            Error::DeleteMessageBatch(_, error) => Some(&**error),
+            Error::Io(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Describes what went wrong with an SQS request more readably than rusoto's Debug output, e.g.
+// `QueueDoesNotExist: The specified queue does not exist` rather than
+// `Service(QueueDoesNotExist("The specified queue does not exist"))`
+pub(crate) struct Cause<'a, E>(pub(crate) &'a RusotoError<E>);
+
+impl<E: std::error::Error + 'static> fmt::Display for Cause<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            RusotoError::Service(error) => {
+                // The variant name is SQS's error code, and only Debug gives it away
+                let debug = format!("{:?}", error);
+                let code = debug.split('(').next().unwrap_or_default();
+                write!(f, "{}: {}", code, error)?;
+                write_kms_hint(f, code)
+            }
+            RusotoError::HttpDispatch(error) => write!(f, "couldn't reach SQS: {}", error),
+            RusotoError::Credentials(error) => write!(f, "no usable credentials: {}", error),
+            RusotoError::Validation(reason) | RusotoError::ParseError(reason) => {
+                write!(f, "{}", reason)
+            }
+            RusotoError::Unknown(response) => match error_response(&response.body) {
+                Some((code, message)) => {
+                    write!(f, "{}: {}", code, message)?;
+                    write_kms_hint(f, &code)
+                }
+                None => write!(
+                    f,
+                    "HTTP {}: {}",
+                    response.status,
+                    response.body_as_str().trim()
+                ),
+            },
+        }
+    }
+}
+
+/// What to check when SQS couldn't use a queue's KMS key, going by the error code, which is
+/// `KMS.AccessDeniedException` for a message that failed to send or `KmsAccessDenied` for a
+/// whole request. KMS errors are otherwise easy to mistake for problems with SQS itself.
+pub(crate) fn kms_hint(code: &str) -> Option<&'static str> {
+    let code = code.to_ascii_lowercase();
+    let reason = code
+        .strip_prefix("kms.")
+        .or_else(|| code.strip_prefix("kms"))?;
+    Some(if reason.starts_with("accessdenied") {
+        "the queue's KMS key policy must allow kms:GenerateDataKey and kms:Decrypt for these credentials"
+    } else if reason.starts_with("disabled") || reason.starts_with("invalidstate") {
+        "the queue's KMS key is disabled or pending deletion"
+    } else if reason.starts_with("notfound") {
+        "the queue's KMS key doesn't exist, check its KmsMasterKeyId attribute"
+    } else if reason.starts_with("throttl") {
+        "KMS is throttling requests, consider raising the queue's KmsDataKeyReusePeriodSeconds"
+    } else {
+        "check the queue's KMS key and its key policy"
+    })
+}
+
+fn write_kms_hint(f: &mut fmt::Formatter, code: &str) -> fmt::Result {
+    match kms_hint(code) {
+        Some(hint) => write!(f, " ({})", hint),
+        None => Ok(()),
+    }
+}
+
+// The code and message from an SQS error response rusoto didn't recognise, which is how
+// errors like access being denied come back
+fn error_response(body: &[u8]) -> Option<(String, String)> {
+    let mut code = None;
+    let mut message = None;
+    let mut element = String::new();
+    for event in EventReader::new(body) {
+        match event.ok()? {
+            XmlEvent::StartElement { name, .. } => element = name.local_name,
+            XmlEvent::Characters(text) => match element.as_str() {
+                "Code" => code = Some(text),
+                "Message" => message = Some(text),
+                _ => {}
+            },
+            XmlEvent::EndElement { .. } => element.clear(),
+            _ => {}
+        }
+    }
+    Some((code?, message.unwrap_or_default()))
+}
+
+impl From<RusotoError<ListQueuesError>> for Error {
+    fn from(error: RusotoError<ListQueuesError>) -> Error {
+        Error::ListQueues(Box::new(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::request::BufferedHttpResponse;
+    use rusoto_core::HttpDispatchError;
+
+    #[test]
+    fn describes_sqs_errors_with_their_code_and_message() {
+        let error = Error::ReceiveMessage(
+            String::from("https://sqs.eu-west-1.amazonaws.com/123456789012/source"),
+            Box::new(RusotoError::Service(ReceiveMessageError::OverLimit(
+                String::from("Too many messages in flight"),
+            ))),
+        );
+        assert_eq!(
+            error.to_string(),
+            "Failed to receive messages from https://sqs.eu-west-1.amazonaws.com/123456789012/source: OverLimit: Too many messages in flight"
+        );
+
+        let error = Error::ListQueues(Box::new(RusotoError::HttpDispatch(HttpDispatchError::new(
+            String::from("connection refused"),
+        ))));
+        assert_eq!(
+            error.to_string(),
+            "Failed to list queues: couldn't reach SQS: connection refused"
+        );
+    }
+
+    #[test]
+    fn exposes_the_underlying_error_as_its_source() {
+        use std::error::Error as _;
+
+        let error = Error::ReceiveMessage(
+            String::from("https://sqs.eu-west-1.amazonaws.com/123456789012/source"),
+            Box::new(RusotoError::Service(ReceiveMessageError::OverLimit(
+                String::from("Too many messages in flight"),
+            ))),
+        );
+        assert!(matches!(
+            error
+                .source()
+                .and_then(|source| source.downcast_ref::<RusotoError<ReceiveMessageError>>()),
+            Some(RusotoError::Service(ReceiveMessageError::OverLimit(_)))
+        ));
+
+        let error = Error::Io(
+            String::from("messages.jsonl"),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(
+            error
+                .source()
+                .and_then(|source| source.downcast_ref::<io::Error>())
+                .map(io::Error::kind),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        assert!(Error::NothingToReplay.source().is_none());
+    }
+
+    #[test]
+    fn points_kms_errors_at_the_key_policy() {
+        let error = Error::SendMessageBatch(
+            String::from("https://sqs.eu-west-1.amazonaws.com/123456789012/destination"),
+            Box::new(RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: b"<ErrorResponse><Error><Code>KmsAccessDenied</Code><Message>Access denied</Message></Error></ErrorResponse>"
+                    .to_vec()
+                    .into(),
+                headers: Default::default(),
+            })),
+        );
+        assert_eq!(
+            error.to_string(),
+            "Failed to send messages to https://sqs.eu-west-1.amazonaws.com/123456789012/destination: KmsAccessDenied: Access denied (the queue's KMS key policy must allow kms:GenerateDataKey and kms:Decrypt for these credentials)"
+        );
+
+        assert!(kms_hint("KMS.AccessDeniedException")
+            .is_some_and(|hint| hint.contains("kms:GenerateDataKey")));
+        assert!(kms_hint("KMS.NotFoundException").is_some_and(|hint| hint.contains("exist")));
+        assert_eq!(kms_hint("AccessDenied"), None);
+        assert_eq!(kms_hint("InternalError"), None);
+    }
+
+    #[test]
+    fn reads_the_code_from_unrecognised_error_responses() {
+        let body = r#"<ErrorResponse>
+          <Error>
+            <Type>Sender</Type>
+            <Code>AccessDenied</Code>
+            <Message>Access to the resource is denied.</Message>
+          </Error>
+        </ErrorResponse>"#;
+
+        assert_eq!(
+            error_response(body.as_bytes()),
+            Some((
+                String::from("AccessDenied"),
+                String::from("Access to the resource is denied.")
+            ))
+        );
+        assert_eq!(error_response(b"Service Unavailable"), None);
+    }
+}
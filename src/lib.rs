@@ -0,0 +1,42 @@
+mod checkpoint;
+mod client;
+mod compat;
+mod config;
+mod dump;
+mod error;
+mod load;
+mod logger;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod output;
+mod peek;
+mod queues;
+mod replay;
+mod report;
+mod retry;
+mod shutdown;
+mod sts;
+
+pub use client::{sqs_client, CredentialOptions, CredentialSource};
+pub use config::{load_config, Config};
+pub use dump::{dump, DumpOptions};
+pub use error::{Error, ExitCode};
+pub use load::{load, LoadOptions};
+pub use logger::init_logger;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+pub use output::{Event, Output, OutputFormat};
+pub use peek::peek;
+pub use queues::{
+    count_messages, create_queue, dead_letter_source_queue, list_queues, purge_queue, queue_url,
+    validate_queue_url, CreateQueueOptions, MAX_LIST_RESULTS, MAX_RETENTION_SECONDS,
+};
+pub use replay::{
+    replay, Approval, ApproveBatch, DedupStrategy, EmptyBodies, FifoRetryStrategy, PartialBatch,
+    ReplayOptions, ReplaySummary, StopReason, DEFAULT_EMPTY_RECEIVES, MAX_BATCH_SIZE,
+    MAX_DELAY_SECONDS, MAX_MESSAGE_SIZE, MAX_VISIBILITY_TIMEOUT, MAX_WAIT_TIME_SECONDS,
+    ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE,
+};
+pub use retry::RetryPolicy;
+pub use shutdown::Shutdown;
+pub use sts::AssumeRole;
@@ -0,0 +1,226 @@
+use crate::error::Error;
+use crate::output::{Event, Output};
+use crate::queues::{check_kms_key, queue_details};
+use crate::replay::{
+    fifo_ids, send_message_batch, DedupStrategy, FifoIds, FifoRetryStrategy, MAX_BATCH_SIZE,
+};
+use crate::retry::RetryPolicy;
+use rusoto_sqs::{Message, MessageAttributeValue, Sqs};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// What to load and where to send it.
+pub struct LoadOptions {
+    /// A file written by `dump`, with one JSON message per line
+    pub input_file: PathBuf,
+    pub dest_url: String,
+    /// Required when the destination is a FIFO queue and ignored otherwise
+    pub message_group_id: Option<String>,
+    /// Stop at the first malformed line instead of skipping it
+    pub strict: bool,
+    pub retry_policy: RetryPolicy,
+}
+
+/// Sends every message in a dump file to the destination queue, returning the number sent. It's
+/// an error if any couldn't be sent, once those SQS failed through no fault of their own have
+/// been retried.
+pub async fn load(client: &dyn Sqs, options: LoadOptions, output: &Output) -> Result<usize, Error> {
+    let dest_url = options.dest_url.as_str();
+    let path = options.input_file.display().to_string();
+    let io_error = |error| Error::Io(path.clone(), error);
+    let file = BufReader::new(File::open(&options.input_file).map_err(io_error)?);
+
+    let details = queue_details(client, dest_url, &options.retry_policy, output).await?;
+    check_kms_key(&details, dest_url, output);
+    let fifo_ids = fifo_ids(
+        &details,
+        FifoIds {
+            group_id: options.message_group_id.clone(),
+            group_id_attribute: None,
+            dedup_id_attribute: None,
+            dedup_strategy: DedupStrategy::Random,
+            content_based_deduplication: details.content_based_deduplication,
+            source_group_id: false,
+            retry_strategy: FifoRetryStrategy::Send,
+            recently_sent: RefCell::default(),
+            over_limit: RefCell::default(),
+        },
+        output,
+    )?;
+
+    let mut loaded = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+    let mut batch = Vec::new();
+    let mut lines = file.lines().enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        let line = line.map_err(io_error)?;
+        if !line.trim().is_empty() {
+            match message_from_json(&line) {
+                Ok(message) => batch.push(message),
+                Err(reason) if options.strict => {
+                    return Err(Error::MalformedLine(path.clone(), i + 1, reason))
+                }
+                Err(reason) => {
+                    skipped += 1;
+                    output.emit(Event::Warning {
+                        message: &format!("Skipping line {} of {}: {}", i + 1, path, reason),
+                    });
+                }
+            }
+        }
+
+        if batch.len() as i64 == MAX_BATCH_SIZE || (lines.peek().is_none() && !batch.is_empty()) {
+            // Deduplication IDs are regenerated so FIFO destinations don't drop messages that
+            // were sent once before
+            let mut pending: Vec<&Message> = batch.iter().collect();
+            let mut sent = 0;
+            let mut attempt = 0;
+            loop {
+                let batch_sent = send_message_batch(
+                    client,
+                    dest_url,
+                    &pending,
+                    &fifo_ids,
+                    None,
+                    &options.retry_policy,
+                    output,
+                )
+                .await?;
+                sent += batch_sent.messages.len();
+                if batch_sent.retryable.is_empty() || attempt == options.retry_policy.max_retries {
+                    break;
+                }
+                let delay = options.retry_policy.delay(attempt);
+                attempt += 1;
+                output.emit(Event::Warning {
+                    message: &format!(
+                        "{} messages failed to send to {}, retrying them in {}ms (attempt {} of {})",
+                        batch_sent.retryable.len(),
+                        dest_url,
+                        delay.as_millis(),
+                        attempt,
+                        options.retry_policy.max_retries
+                    ),
+                });
+                tokio::time::delay_for(delay).await;
+                pending = batch_sent.retryable;
+            }
+            loaded += sent;
+            failed += batch.len() - sent;
+            batch.clear();
+        }
+    }
+
+    output.emit(Event::LoadFinished {
+        loaded,
+        skipped,
+        failed,
+    });
+    if failed > 0 {
+        return Err(Error::MessagesNotLoaded(failed, loaded));
+    }
+    Ok(loaded)
+}
+
+// The reverse of `dump::message_to_json`. Only the body is required.
+fn message_from_json(line: &str) -> Result<Message, String> {
+    let value: Value = serde_json::from_str(line).map_err(|error| error.to_string())?;
+    let body = match value.get("body") {
+        Some(Value::String(body)) => body.clone(),
+        _ => return Err(String::from("missing message body")),
+    };
+    let message_attributes = match value.get("message_attributes") {
+        Some(Value::Object(attributes)) => {
+            let mut message_attributes = HashMap::new();
+            for (name, attribute) in attributes {
+                message_attributes.insert(name.clone(), message_attribute_from_json(attribute)?);
+            }
+            Some(message_attributes)
+        }
+        Some(Value::Null) | None => None,
+        Some(_) => return Err(String::from("message attributes must be an object")),
+    };
+
+    Ok(Message {
+        message_id: value
+            .get("message_id")
+            .and_then(Value::as_str)
+            .map(String::from),
+        body: Some(body),
+        message_attributes,
+        ..Default::default()
+    })
+}
+
+fn message_attribute_from_json(attribute: &Value) -> Result<MessageAttributeValue, String> {
+    let data_type = match attribute.get("data_type") {
+        Some(Value::String(data_type)) => data_type.clone(),
+        _ => return Err(String::from("message attribute is missing its data type")),
+    };
+    let binary_value = match attribute.get("binary_value").and_then(Value::as_str) {
+        Some(encoded) => Some(
+            base64::decode(encoded)
+                .map_err(|error| format!("invalid binary attribute value: {}", error))?
+                .into(),
+        ),
+        None => None,
+    };
+
+    Ok(MessageAttributeValue {
+        data_type,
+        string_value: attribute
+            .get("string_value")
+            .and_then(Value::as_str)
+            .map(String::from),
+        binary_value,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::message_to_json;
+
+    #[test]
+    fn reads_messages_written_by_dump() {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            String::from("colour"),
+            MessageAttributeValue {
+                data_type: String::from("String"),
+                string_value: Some(String::from("orange")),
+                ..Default::default()
+            },
+        );
+        attributes.insert(
+            String::from("checksum"),
+            MessageAttributeValue {
+                data_type: String::from("Binary"),
+                binary_value: Some(vec![0xde, 0xad, 0xbe, 0xef].into()),
+                ..Default::default()
+            },
+        );
+        let message = Message {
+            message_id: Some(String::from("1")),
+            body: Some(String::from("{\"hello\": \"world\"}")),
+            message_attributes: Some(attributes),
+            ..Default::default()
+        };
+
+        let line = message_to_json(&message).to_string();
+
+        assert_eq!(message_from_json(&line), Ok(message));
+    }
+
+    #[test]
+    fn rejects_lines_without_a_body() {
+        assert!(message_from_json("{\"message_id\": \"1\"}").is_err());
+        assert!(message_from_json("not json").is_err());
+    }
+}
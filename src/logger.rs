@@ -0,0 +1,60 @@
+use crate::output::DATA_TARGET;
+use chrono::{Local, SecondsFormat};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Everything logged by this crate has a target starting with its name
+const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+
+/// Prints the data commands produce to stdout and everything else to stderr, so the output can
+/// be piped into other tools. Records from other crates, like rusoto, are prefixed with where
+/// they came from since they aren't written with this tool's output in mind.
+struct Logger {
+    // Only lines on stderr are timestamped, so the data on stdout can still be piped elsewhere
+    timestamps: AtomicBool,
+}
+
+impl Log for Logger {
+    // Other crates' debug logging is noisy and includes signed request headers, so only their
+    // warnings and errors are worth showing
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with(CRATE_NAME) || metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = if record.target().starts_with(CRATE_NAME) {
+            record.args().to_string()
+        } else {
+            format!("[{} {}] {}", record.level(), record.target(), record.args())
+        };
+        if record.target() == DATA_TARGET {
+            println!("{}", line);
+        } else if self.timestamps.load(Ordering::Relaxed) {
+            eprintln!(
+                "{} {}",
+                Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+                line
+            );
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger {
+    timestamps: AtomicBool::new(false),
+};
+
+/// Installs the logger that human output is written through, showing records up to `level`.
+/// With `timestamps`, each status line starts with the local time in ISO 8601 format.
+pub fn init_logger(level: LevelFilter, timestamps: bool) -> Result<(), SetLoggerError> {
+    LOGGER.timestamps.store(timestamps, Ordering::Relaxed);
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -1,200 +1,1564 @@
-use clap::{App, Arg, SubCommand};
-use colored::*;
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgMatches, Shell, SubCommand};
+use log::LevelFilter;
+use regex::Regex;
 use rusoto_core::Region;
-use rusoto_sqs::{
-    DeleteMessageRequest, ListQueuesRequest, ReceiveMessageRequest, SendMessageRequest, Sqs,
-    SqsClient,
+use rusoto_sqs::{Message, SqsClient};
+#[cfg(feature = "metrics")]
+use sqs_replay::Metrics;
+use sqs_replay::{
+    count_messages, create_queue, dead_letter_source_queue, dump, init_logger, list_queues, load,
+    load_config, peek, purge_queue, queue_url, replay, sqs_client, validate_queue_url, Approval,
+    AssumeRole, Config, CreateQueueOptions, CredentialOptions, CredentialSource, DedupStrategy,
+    DumpOptions, EmptyBodies, Error, Event, FifoRetryStrategy, LoadOptions, Output, OutputFormat,
+    PartialBatch, ReplayOptions, ReplaySummary, RetryPolicy, Shutdown, DEFAULT_EMPTY_RECEIVES,
+    MAX_BATCH_SIZE, MAX_DELAY_SECONDS, MAX_LIST_RESULTS, MAX_RETENTION_SECONDS,
+    MAX_VISIBILITY_TIMEOUT, MAX_WAIT_TIME_SECONDS,
 };
-use uuid::Uuid;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() {
-    let matches = App::new("AWS SQS Replay CLI")
-        .version("0.1.0")
-        .about("Read messages from one queue and send them to another")
-        .subcommand(
-            SubCommand::with_name("send")
-                .about("Send messages")
-                .arg(
-                    Arg::with_name("source-queue-url")
-                        .short("s")
-                        .long("source-queue-url")
-                        .value_name("source-queue-url")
-                        .help("The source SQS queue URL")
-                        .required(true)
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("destination-queue-url")
-                        .short("d")
-                        .long("destination-queue-url")
-                        .value_name("destination-queue-url")
-                        .help("The destination SQS queue URL")
-                        .required(true)
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("message-group-id")
-                        .short("g")
-                        .long("message-group-id")
-                        .value_name("message-group-id")
-                        .help("Message Group ID to use when sending to the destination queue")
-                        .required(true)
-                        .takes_value(true),
-                ),
-        )
-        .subcommand(SubCommand::with_name("list-queues").about("List SQS Queue URLs"))
-        .get_matches();
+    // The config file provides the defaults for other arguments, so it has to be read before
+    // they're parsed
+    let mut config = match load_config(config_path().as_deref()) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(error.exit_code() as i32);
+        }
+    };
 
-    let client = SqsClient::new(Region::EuWest1);
-    if let Some(matches) = matches.subcommand_matches("send") {
-        let source_url = matches.value_of("source-queue-url").unwrap();
-        let dest_url = matches.value_of("destination-queue-url").unwrap();
-        let message_group_id = matches.value_of("message-group-id").unwrap();
-        replay_messages(&client, source_url, dest_url, message_group_id);
-    } else if let Some(_) = matches.subcommand_matches("list-queues") {
-        list_queues(&client);
-    } else {
-        println!("Missing required subcommand");
-        std::process::exit(1);
+    // As with the AWS CLI, AWS_REGION wins over AWS_DEFAULT_REGION, and both win over the file
+    config.region = env::var("AWS_DEFAULT_REGION").ok().or(config.region);
+
+    let matches = app(&config).get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let shell = matches.value_of("shell").unwrap().parse().unwrap();
+        app(&config).gen_completions_to(env!("CARGO_BIN_NAME"), shell, &mut io::stdout());
+        return;
     }
-}
 
-fn list_queues(client: &SqsClient) {
-    let list_input: ListQueuesRequest = Default::default();
+    // colored already turns itself off for NO_COLOR and when stdout isn't a terminal, but
+    // status lines go to stderr
+    if matches.is_present("no-color") || !atty::is(atty::Stream::Stderr) {
+        colored::control::set_override(false);
+    }
 
-    match client.list_queues(list_input).sync() {
-        Ok(queues) => match queues.queue_urls {
-            Some(urls) => {
-                for u in urls.iter() {
-                    println!("{}", u);
-                }
+    let quiet = matches.is_present("quiet");
+    init_logger(
+        match matches.occurrences_of("verbose") {
+            _ if quiet => LevelFilter::Error,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        },
+        !matches.is_present("no-timestamps"),
+    )
+    .expect("Failed to install logger");
+    let output = Output::new(match matches.value_of("output") {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Human,
+    })
+    .with_quiet(quiet)
+    .with_pretty(matches.is_present("pretty"))
+    .with_max_body_len(
+        Some(matches.value_of("max-print-len").unwrap().parse().unwrap())
+            .filter(|max_len| *max_len > 0),
+    )
+    .with_receipt_handles(matches.is_present("show-receipt-handle"))
+    .with_progress_json(matches.is_present("progress-json"))
+    .with_progress(atty::is(atty::Stream::Stderr));
+    #[cfg(feature = "metrics")]
+    let output = match matches.value_of("metrics-addr") {
+        Some(addr) => match Metrics::connect(addr) {
+            Ok(metrics) => output.with_metrics(metrics),
+            Err(error) => {
+                let error = Error::Io(format!("statsd server {}", addr), error);
+                output.emit(Event::Error {
+                    message: &error.to_string(),
+                });
+                std::process::exit(error.exit_code() as i32);
             }
-            None => println!("No queues"),
         },
-        Err(error) => {
-            println!("Failed to list queues: {:?}", error);
-            std::process::exit(2);
-        }
+        None => output,
+    };
+
+    if let Err(error) = run(&matches, &output).await {
+        output.emit(Event::Error {
+            message: &error.to_string(),
+        });
+        std::process::exit(error.exit_code() as i32);
     }
 }
 
-fn replay_messages(client: &SqsClient, source_url: &str, dest_url: &str, message_group_id: &str) {
-    println!(" {} {}", "     Source queue URL".green(), source_url);
-    println!(" {} {}", "Destination queue URL".green(), dest_url);
-    println!("");
+const EXIT_CODES: &str = "EXIT CODES:
+    0    Success
+    1    Invalid arguments, config or credentials
+    2    Failed to list, look up or inspect a queue
+    3    Failed to receive messages
+    4    Failed to send messages, and none were replayed
+    5    Failed to delete messages
+    6    Some messages were replayed but others were left in the source queue
+    7    Failed to read or write a file
+    8    Nothing to replay (--fail-if-empty)
+    130  Interrupted by a second Ctrl-C";
 
-    let mut more_messages = true;
-    let mut batch_no = 1;
+// Built by a function so completions can be generated from the same definition
+fn app(config: &Config) -> App<'_, '_> {
+    let app = App::new("AWS SQS Replay CLI")
+    .version("0.1.0")
+    .about("Read messages from one queue and send them to another")
+    .after_help(EXIT_CODES)
+    .arg(
+        Arg::with_name("config")
+            .long("config")
+            .value_name("path")
+            .help("Config file with defaults for region, profile, batch_size, wait_time and visibility_timeout (defaults to ~/.sqs-replay.toml). Only flat key = value lines are supported, with \"quoted\" strings that have no escapes, integers and # comments")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(with_default(
+        Arg::with_name("region")
+            .short("r")
+            .long("region")
+            .value_name("region")
+            .help("The AWS region of the queues, also read from AWS_DEFAULT_REGION (defaults to eu-west-1)")
+            .env("AWS_REGION")
+            .global(true)
+            .takes_value(true),
+        &config.region,
+    ))
+    .arg(with_default(
+        Arg::with_name("profile")
+            .long("profile")
+            .value_name("profile")
+            .help("The profile in ~/.aws/credentials to use (defaults to the standard credential chain)")
+            .env("AWS_PROFILE")
+            .global(true)
+            .takes_value(true),
+        &config.profile,
+    ))
+    .arg(
+        Arg::with_name("credentials")
+            .long("credentials")
+            .value_name("source")
+            .help("Where to get AWS credentials from. The chain tries --profile, web identity, environment variables, the default profile and then the ECS or EC2 role. For AWS SSO, use a profile with credential_process = aws configure export-credentials --profile <sso-profile> --format process.")
+            .possible_values(&["chain", "profile", "env", "web-identity"])
+            .default_value("chain")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("assume-role-arn")
+            .long("assume-role-arn")
+            .value_name("assume-role-arn")
+            .help("An IAM role to assume before talking to SQS, e.g. to reach queues in another account")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("external-id")
+            .long("external-id")
+            .value_name("external-id")
+            .help("The external ID the role expects when it's assumed")
+            .requires("assume-role-arn")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("session-name")
+            .long("session-name")
+            .value_name("session-name")
+            .help("The name of the assumed role session, as it appears in CloudTrail")
+            .default_value("sqs-replay")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("output")
+            .help("Output format")
+            .possible_values(&["human", "json"])
+            .default_value("human")
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .global(true)
+            .help("Show each message as it's replayed, -vv to include message bodies"),
+    )
+    .arg(
+        Arg::with_name("quiet")
+            .long("quiet")
+            .conflicts_with("verbose")
+            .global(true)
+            .help("Only report errors"),
+    )
+    .arg(
+        Arg::with_name("no-timestamps")
+            .long("no-timestamps")
+            .global(true)
+            .help("Don't start status lines with the time"),
+    )
+    .arg(
+        Arg::with_name("no-color")
+            .long("no-color")
+            .global(true)
+            .help("Don't color output. Also turned off by setting NO_COLOR, or when output isn't a terminal."),
+    )
+    .arg(
+        Arg::with_name("progress-json")
+            .long("progress-json")
+            .global(true)
+            .help("Write progress to stderr as one JSON object per line, for wrapper scripts. Best with --output json, as human output also goes to stderr."),
+    )
+    .arg(
+        Arg::with_name("pretty")
+            .long("pretty")
+            .global(true)
+            .help("Indent message bodies that are JSON when printing them"),
+    )
+    .arg(
+        Arg::with_name("max-print-len")
+            .long("max-print-len")
+            .value_name("characters")
+            .global(true)
+            .help("Cut printed message bodies off after this many characters, or 0 to print them in full. JSON output and the messages sent on always have the whole body.")
+            .default_value("2048")
+            .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|_| String::from("must be a non-negative integer")))
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("show-receipt-handle")
+            .long("show-receipt-handle")
+            .global(true)
+            .help("Print each received message's receipt handle, for debugging deletes"),
+    )
+    .arg(
+        Arg::with_name("max-retries")
+            .long("max-retries")
+            .value_name("max-retries")
+            .help("Number of times to retry a request after a transient SQS error")
+            .default_value("3")
+            .validator(|v| match v.parse::<u32>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("must be a non-negative integer")),
+            })
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("request-timeout")
+            .long("request-timeout")
+            .value_name("seconds")
+            .help("Seconds to wait for each SQS request before retrying it, on top of any long polling wait time")
+            .validator(|v| match v.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(()),
+                _ => Err(String::from("must be a positive integer")),
+            })
+            .global(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("endpoint-url")
+            .short("e")
+            .long("endpoint-url")
+            .value_name("endpoint-url")
+            .help("A custom SQS endpoint to use instead of AWS, e.g. http://localhost:4566 for LocalStack. Also read from AWS_ENDPOINT_URL.")
+            .env("AWS_ENDPOINT_URL_SQS")
+            .global(true)
+            .takes_value(true),
+    )
+    .subcommand(
+        SubCommand::with_name("send")
+            .about("Send messages")
+            .arg(
+                Arg::with_name("source-queue-url")
+                    .short("s")
+                    .long("source-queue-url")
+                    .value_name("source-queue-url")
+                    .help("The source SQS queue URL, can be given more than once to receive from several queues in turn")
+                    .required_unless("source-queue-name")
+                    .conflicts_with("source-queue-name")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("source-queue-name")
+                    .long("source-queue-name")
+                    .value_name("source-queue-name")
+                    .help("The source SQS queue name, instead of its URL, can be given more than once")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-queue-url")
+                    .short("d")
+                    .long("destination-queue-url")
+                    .value_name("destination-queue-url")
+                    .help("The destination SQS queue URL, can be given more than once to send each message to several queues")
+                    .required_unless("destination-queue-name")
+                    .conflicts_with("destination-queue-name")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-queue-name")
+                    .long("destination-queue-name")
+                    .value_name("destination-queue-name")
+                    .help("The destination SQS queue name, instead of its URL, can be given more than once")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("source-region")
+                    .long("source-region")
+                    .value_name("source-region")
+                    .help("The AWS region of the source queue (defaults to --region)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-region")
+                    .long("destination-region")
+                    .value_name("destination-region")
+                    .help("The AWS region of the destination queue (defaults to --region)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("source-endpoint-url")
+                    .long("source-endpoint-url")
+                    .value_name("endpoint-url")
+                    .help("A custom SQS endpoint for the source queue (defaults to --endpoint-url)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-endpoint-url")
+                    .long("destination-endpoint-url")
+                    .value_name("endpoint-url")
+                    .help("A custom SQS endpoint for the destination queue (defaults to --endpoint-url)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("message-group-id")
+                    .short("g")
+                    .long("message-group-id")
+                    .value_name("message-group-id")
+                    .help("Message Group ID to use when sending to a FIFO destination queue")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("group-id-attribute")
+                    .long("group-id-attribute")
+                    .value_name("group-id-attribute")
+                    .help("Message attribute to take each message's group ID from, falling back to --message-group-id")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("dedup-id-attribute")
+                    .long("dedup-id-attribute")
+                    .value_name("dedup-id-attribute")
+                    .help("Message attribute to take each message's deduplication ID from when sending to a FIFO queue")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("dedup-strategy")
+                    .long("dedup-strategy")
+                    .value_name("dedup-strategy")
+                    .help("How to generate deduplication IDs for FIFO destinations, message-id and body-hash make re-runs within five minutes safe")
+                    .possible_values(&["random", "message-id", "body-hash"])
+                    .default_value("random")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-messages")
+                    .short("n")
+                    .long("max-messages")
+                    .value_name("max-messages")
+                    .help("Stop after replaying this many messages")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-duration")
+                    .long("max-duration")
+                    .value_name("duration")
+                    .help("Stop receiving after this long, e.g. 90s, 30m or 1h30m, and finish the batches in flight")
+                    .validator(|v| parse_duration(&v).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("batch-size")
+                    .short("b")
+                    .long("batch-size")
+                    .value_name("batch-size")
+                    .help("Number of messages to request in each receive (1-10)")
+                    .default_value(config.batch_size.as_deref().unwrap_or("10"))
+                    .validator(|v| match v.parse::<i64>() {
+                        Ok(n) if (1..=MAX_BATCH_SIZE).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 1 and {}", MAX_BATCH_SIZE)),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("wait-time")
+                    .short("w")
+                    .long("wait-time")
+                    .value_name("wait-time")
+                    .help("Seconds each receive waits for messages to arrive (0-20, 0 for short polling)")
+                    .default_value(config.wait_time.as_deref().unwrap_or("3"))
+                    .validator(|v| match v.parse::<i64>() {
+                        Ok(n) if (0..=MAX_WAIT_TIME_SECONDS).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 0 and {}", MAX_WAIT_TIME_SECONDS)),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-empty-receives")
+                    .long("max-empty-receives")
+                    .value_name("count")
+                    .help("Consecutive empty receives before the source queue is considered drained. Raise it to drain queues with sparse traffic more thoroughly.")
+                    .default_value("2")
+                    .validator(|v| match v.parse::<u32>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("interactive")
+                    .long("interactive")
+                    .help("Show each batch and ask before sending it. Ignored when stdin isn't a terminal.")
+                    .conflicts_with("dry-run"),
+            )
+            .arg(
+                Arg::with_name("follow")
+                    .long("follow")
+                    .visible_alias("tail")
+                    .help("Keep forwarding new messages as they arrive until interrupted, rather than stopping once the source is drained")
+                    .conflicts_with("dry-run"),
+            )
+            .arg(
+                Arg::with_name("wait-for-messages")
+                    .long("wait-for-messages")
+                    .help("Wait for the first message to arrive before draining the source, rather than stopping straight away if it's empty"),
+            )
+            .arg(
+                Arg::with_name("startup-timeout")
+                    .long("startup-timeout")
+                    .value_name("duration")
+                    .help("Stop waiting for the first message after this long, like 10m")
+                    .validator(|v| parse_duration(&v).map(|_| ()))
+                    .requires("wait-for-messages")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("batch-window")
+                    .long("batch-window")
+                    .value_name("duration")
+                    .help("Hold messages back for up to this long, like 5s, so they're sent in full batches of 10. Checked after each receive.")
+                    .validator(|v| parse_duration(&v).map(|_| ()))
+                    .conflicts_with("preserve-order")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("visibility-timeout")
+                    .long("visibility-timeout")
+                    .value_name("visibility-timeout")
+                    .help("Seconds to hide received messages from other consumers while they're replayed")
+                    .default_value(config.visibility_timeout.as_deref().unwrap_or("5"))
+                    .validator(|v| match v.parse::<i64>() {
+                        Ok(n) if (0..=MAX_VISIBILITY_TIMEOUT).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 0 and {}", MAX_VISIBILITY_TIMEOUT)),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("delay-seconds")
+                    .long("delay-seconds")
+                    .value_name("delay-seconds")
+                    .help("Seconds to delay delivery of replayed messages by (0-900, standard queues only)")
+                    .validator(|v| match v.parse::<i64>() {
+                        Ok(n) if (0..=MAX_DELAY_SECONDS).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 0 and {}", MAX_DELAY_SECONDS)),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("delay-jitter")
+                    .long("delay-jitter")
+                    .value_name("min-max")
+                    .help("Delay each replayed message by a random number of seconds in this range, e.g. 0-300, to spread out when they become visible (standard queues only)")
+                    .validator(|v| parse_delay_range(&v).map(|_| ()))
+                    .conflicts_with("delay-seconds")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("concurrency")
+                    .short("c")
+                    .long("concurrency")
+                    .visible_alias("max-inflight-batches")
+                    .value_name("concurrency")
+                    .help("Number of batches to send and delete at once (always 1 for FIFO queues unless --concurrency-per-group is set). No more are received until one finishes, so a slow destination holds receiving back.")
+                    .default_value("1")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("prefetch")
+                    .long("prefetch")
+                    .value_name("batches")
+                    .help("Receive up to this many batches ahead of the ones being sent, so a slow receive doesn't leave the destination idle. Queued messages use up their visibility timeout while they wait.")
+                    .default_value("0")
+                    .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|_| String::from("must be a non-negative integer")))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("concurrency-per-group")
+                    .long("concurrency-per-group")
+                    .help("Send up to --concurrency batches at once to FIFO queues too, one at a time within each message group, so groups don't wait for each other but each one keeps its order")
+                    .conflicts_with("preserve-order"),
+            )
+            .arg(
+                Arg::with_name("preserve-order")
+                    .long("preserve-order")
+                    .help("Replay one message at a time, keeping each message's FIFO group ID, so the destination gets them in exactly the order they were received. Disables batching and --concurrency.")
+                    .conflicts_with("continue-on-error"),
+            )
+            .arg(
+                Arg::with_name("checkpoint-file")
+                    .long("checkpoint-file")
+                    .value_name("path")
+                    .help("Record the IDs of replayed messages in this file, and skip any already in it, so an interrupted replay can be resumed")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("report-file")
+                    .long("report-file")
+                    .value_name("path")
+                    .help("Append a CSV row to this file for each message sent or that failed to send, as an audit trail")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rate-limit")
+                    .long("rate-limit")
+                    .value_name("messages-per-second")
+                    .help("Maximum number of messages to send per second")
+                    .validator(|v| match v.parse::<f64>() {
+                        Ok(n) if n > 0.0 && n.is_finite() => Ok(()),
+                        _ => Err(String::from("must be a positive number")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("continue-on-error")
+                    .long("continue-on-error")
+                    .help("Leave messages that fail to send in the source queue and carry on, instead of stopping"),
+            )
+            .arg(
+                Arg::with_name("copy")
+                    .long("copy")
+                    .alias("no-delete")
+                    .help("Leave messages in the source queue after sending them"),
+            )
+            .arg(
+                Arg::with_name("filter-regex")
+                    .long("filter-regex")
+                    .value_name("filter-regex")
+                    .help("Only replay messages with a body matching this regular expression, leaving the rest in the source queue")
+                    .validator(|v| Regex::new(&v).map(|_| ()).map_err(|error| error.to_string()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("filter-attribute")
+                    .long("filter-attribute")
+                    .value_name("name=value")
+                    .help("Only replay messages with this string attribute value, can be given more than once")
+                    .validator(|v| parse_attribute_filter(&v).map(|_| ()))
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("attribute-names")
+                    .long("attribute-names")
+                    .value_name("names")
+                    .help("Comma-separated message attributes to receive and replay, or All to keep all of them (defaults to none). Attributes the filters or FIFO options read are always received.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("system-attribute-names")
+                    .long("system-attribute-names")
+                    .value_name("names")
+                    .help("Comma-separated system attributes to receive, e.g. SentTimestamp,AWSTraceHeader, or All (defaults to none). The trace header is only forwarded if AWSTraceHeader is received. Attributes the other options read are always received.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("since")
+                    .long("since")
+                    .value_name("time")
+                    .help("Only replay messages sent at or after this time, either RFC 3339 like 2024-05-01T09:00:00Z or a duration ago like 2h")
+                    .validator(|v| parse_time(&v).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("until")
+                    .long("until")
+                    .value_name("time")
+                    .help("Only replay messages sent before this time, either RFC 3339 or a duration ago")
+                    .validator(|v| parse_time(&v).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("replace")
+                    .long("replace")
+                    .value_name("from=to")
+                    .help("Replace every occurrence of some text in each body before sending it, can be given more than once")
+                    .validator(|v| parse_replacement(&v).map(|_| ()))
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sample-rate")
+                    .long("sample-rate")
+                    .value_name("fraction")
+                    .help("Only replay this fraction of messages, chosen at random, e.g. 0.1 for 10%")
+                    .validator(|v| match v.parse::<f64>() {
+                        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(()),
+                        _ => Err(String::from("must be a number between 0 and 1")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sample-seed")
+                    .long("sample-seed")
+                    .value_name("seed")
+                    .help("Seed for choosing which messages are sampled, for repeatable runs")
+                    .requires("sample-rate")
+                    .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| String::from("must be a non-negative integer")))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("on-empty")
+                    .long("on-empty")
+                    .value_name("on-empty")
+                    .help("What to do with messages with an empty body, which SQS won't send: leave them in the source queue (skip), send <empty> instead (placeholder) or count them as failed (fail)")
+                    .possible_values(&["skip", "placeholder", "fail"])
+                    .default_value("skip")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fifo-retry-strategy")
+                    .long("fifo-retry-strategy")
+                    .value_name("fifo-retry-strategy")
+                    .help("What to do when a message is about to be sent to a FIFO destination with a deduplication ID this replay sent there less than five minutes ago, which SQS would silently drop: send it anyway and count it in the summary (send), wait for the five minutes to pass (wait) or give it a new random deduplication ID (new-id)")
+                    .possible_values(&["send", "wait", "new-id"])
+                    .default_value("send")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("on-partial-batch")
+                    .long("on-partial-batch")
+                    .value_name("on-partial-batch")
+                    .help("What to delete from the source queue when only some of a batch is sent: just the messages that were sent (delete-succeeded), nothing so the whole batch is replayed again (delete-none), or send the failed messages again before deleting whatever was sent (retry)")
+                    .possible_values(&["delete-succeeded", "delete-none", "retry"])
+                    .default_value("delete-succeeded")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("preserve-timestamp")
+                    .long("preserve-timestamp")
+                    .help("Add when each message was originally sent, in milliseconds since the epoch, as an OriginalSentTimestamp message attribute"),
+            )
+            .arg(
+                Arg::with_name("require-fifo")
+                    .long("require-fifo")
+                    .help("Fail before replaying anything unless every destination is a FIFO queue")
+                    .conflicts_with("require-standard"),
+            )
+            .arg(
+                Arg::with_name("require-standard")
+                    .long("require-standard")
+                    .help("Fail before replaying anything unless every destination is a standard queue"),
+            )
+            .arg(
+                Arg::with_name("allow-same-queue")
+                    .long("allow-same-queue")
+                    .help("Allow the source and destination to be the same queue"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .long("force")
+                    .help("Delete messages from the source even if there's no destination to send them to"),
+            )
+            .arg(
+                Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Show what would be replayed without sending or deleting anything"),
+            )
+            .arg(
+                Arg::with_name("fail-if-empty")
+                    .long("fail-if-empty")
+                    .help("Exit with code 8 before replaying anything if the source queues have no messages available"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("redrive")
+            .about("Move messages from a dead-letter queue back to the queue they failed from")
+            .arg(
+                Arg::with_name("dlq-url")
+                    .short("s")
+                    .long("dlq-url")
+                    .value_name("dlq-url")
+                    .help("The dead-letter queue URL")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-queue-url")
+                    .short("d")
+                    .long("destination-queue-url")
+                    .value_name("destination-queue-url")
+                    .help("The queue to move messages to (defaults to the one queue using the dead-letter queue)")
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("message-group-id")
+                    .short("g")
+                    .long("message-group-id")
+                    .value_name("message-group-id")
+                    .help("Message Group ID to use when sending to a FIFO destination queue")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-messages")
+                    .short("n")
+                    .long("max-messages")
+                    .value_name("max-messages")
+                    .help("Stop after redriving this many messages")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-duration")
+                    .long("max-duration")
+                    .value_name("duration")
+                    .help("Stop receiving after this long, e.g. 90s, 30m or 1h30m, and finish the batches in flight")
+                    .validator(|v| parse_duration(&v).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Show what would be redriven without sending or deleting anything"),
+            )
+            .arg(
+                Arg::with_name("fail-if-empty")
+                    .long("fail-if-empty")
+                    .help("Exit with code 8 before redriving anything if the dead-letter queue has no messages available"),
+            )
+            .arg(
+                Arg::with_name("report-file")
+                    .long("report-file")
+                    .value_name("path")
+                    .help("Append a CSV row to this file for each message redriven or that failed to send, as an audit trail")
+                    .takes_value(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("list-queues")
+            .about("List SQS Queue URLs")
+            .arg(
+                Arg::with_name("prefix")
+                    .short("p")
+                    .long("prefix")
+                    .value_name("prefix")
+                    .help("Only list queues whose names start with this prefix")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-results")
+                    .long("max-results")
+                    .value_name("max-results")
+                    .help("Stop after listing this many queues (1-1000)")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if (1..=MAX_LIST_RESULTS).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 1 and {}", MAX_LIST_RESULTS)),
+                    })
+                    .takes_value(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("count")
+            .about("Show approximately how many messages are in a queue")
+            .arg(
+                Arg::with_name("queue-url")
+                    .short("q")
+                    .long("queue-url")
+                    .value_name("queue-url")
+                    .help("The SQS queue URL to count messages in")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("dump")
+            .about("Write messages from a queue to a file, one JSON object per line")
+            .arg(
+                Arg::with_name("queue-url")
+                    .short("q")
+                    .long("queue-url")
+                    .value_name("queue-url")
+                    .help("The SQS queue URL to dump messages from")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("output-file")
+                    .short("f")
+                    .long("output-file")
+                    .value_name("output-file")
+                    .help("The file to write messages to")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("delete")
+                    .long("delete")
+                    .help("Delete messages from the queue once they've been written"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("peek")
+            .about("Print the next messages in a queue with their attributes, without deleting them")
+            .arg(
+                Arg::with_name("queue-url")
+                    .short("q")
+                    .long("queue-url")
+                    .value_name("queue-url")
+                    .help("The SQS queue URL to peek at")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("count")
+                    .short("n")
+                    .long("count")
+                    .value_name("count")
+                    .help("How many messages to print at most")
+                    .default_value("10")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("load")
+            .about("Send messages from a file written by dump to a queue")
+            .arg(
+                Arg::with_name("input-file")
+                    .short("i")
+                    .long("input-file")
+                    .value_name("input-file")
+                    .help("The file to read messages from")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("destination-queue-url")
+                    .short("d")
+                    .long("destination-queue-url")
+                    .value_name("destination-queue-url")
+                    .help("The destination SQS queue URL")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("message-group-id")
+                    .short("g")
+                    .long("message-group-id")
+                    .value_name("message-group-id")
+                    .help("Message Group ID to use when sending to a FIFO destination queue")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Stop at the first malformed line instead of skipping it"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("purge-queue")
+            .about("Delete every message in a queue")
+            .arg(
+                Arg::with_name("queue-url")
+                    .short("q")
+                    .long("queue-url")
+                    .value_name("queue-url")
+                    .help("The SQS queue URL to purge")
+                    .required(true)
+                    .validator(|v| validate_queue_url(&v))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("yes")
+                    .short("y")
+                    .long("yes")
+                    .help("Purge without asking for confirmation"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("create-queue")
+            .about("Create a queue, or print the URL of the existing one with the same settings")
+            .arg(
+                Arg::with_name("queue-name")
+                    .long("queue-name")
+                    .value_name("queue-name")
+                    .help("The name of the queue to create")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fifo")
+                    .long("fifo")
+                    .help("Create a FIFO queue, adding .fifo to the name if it's missing"),
+            )
+            .arg(
+                Arg::with_name("content-based-dedup")
+                    .long("content-based-dedup")
+                    .help("Deduplicate messages by a hash of their body")
+                    .requires("fifo"),
+            )
+            .arg(
+                Arg::with_name("visibility-timeout")
+                    .long("visibility-timeout")
+                    .value_name("visibility-timeout")
+                    .help("Seconds to hide received messages from other consumers")
+                    .validator(|v| match v.parse::<i64>() {
+                        Ok(n) if (0..=MAX_VISIBILITY_TIMEOUT).contains(&n) => Ok(()),
+                        _ => Err(format!("must be between 0 and {}", MAX_VISIBILITY_TIMEOUT)),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("retention")
+                    .long("retention")
+                    .value_name("retention")
+                    .help("How long to keep messages, like 4d or 12h, between 1m and 14d")
+                    .validator(|v| match parse_duration(&v) {
+                        Ok(d) if (60..=MAX_RETENTION_SECONDS).contains(&d.as_secs()) => Ok(()),
+                        Ok(_) => Err(String::from("must be between 1m and 14d")),
+                        Err(error) => Err(error),
+                    })
+                    .takes_value(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("completions")
+            .about("Print a shell completion script")
+            .arg(
+                Arg::with_name("shell")
+                    .help("The shell to generate completions for")
+                    .possible_values(&Shell::variants())
+                    .required(true),
+            ),
+    );
 
-    while more_messages {
-        println!(
-            "{}",
-            format!("Requesting {} messages in batch {}", 10, batch_no).cyan()
-        );
-        let receive_message_input = ReceiveMessageRequest {
-            queue_url: String::from(source_url),
-            max_number_of_messages: Some(10), // TODO: Extract constant
-            wait_time_seconds: Some(3),
-            visibility_timeout: Some(5),
-            receive_request_attempt_id: None, // TODO: Should use this to request the same set of messages in the event of a failure
-            ..Default::default()
-        };
+    #[cfg(feature = "metrics")]
+    let app = app.arg(
+        Arg::with_name("metrics-addr")
+            .long("metrics-addr")
+            .value_name("host:port")
+            .help("Send counters and timings to the statsd server at this address")
+            .global(true)
+            .takes_value(true),
+    );
 
-        match client.receive_message(receive_message_input).sync() {
-            Ok(result) => match result.messages {
-                Some(messages) => {
-                    let count = messages.len();
-                    println!("{}", format!("{} messages received\n", count).cyan());
-
-                    if count == 0 || count < 10 {
-                        more_messages = false;
-                    }
-
-                    for m in messages.iter() {
-                        // println!("{:?}", m);
-
-                        if let Some(message_id) = &m.message_id {
-                            match &m.receipt_handle {
-                                Some(receipt_handle) => {
-                                    let body = &m.body.clone().unwrap_or(String::from("<empty>"));
-                                    println!("{} {}\n{}", "Message ID".green(), message_id, body);
-                                    send_message(
-                                        client,
-                                        dest_url,
-                                        message_id.to_string(),
-                                        body.to_string(),
-                                        Uuid::new_v4().to_string(),
-                                        String::from(message_group_id), // Pass this to the command line as required
-                                    );
-                                    delete_message(client, source_url, receipt_handle);
-                                }
-                                None => {
-                                    println!("Didn't receive receipt handle for Message ID: {} Continuing to next message...", message_id);
-                                }
-                            }
-                        }
-                    }
-                }
-                None => {
-                    println!(
-                        "{}",
-                        format!("No messages received in batch {}", batch_no).cyan()
-                    );
-                    more_messages = false;
-                }
-            },
-            Err(error) => {
-                println!("Failed to list queues: {:?}", error);
-                std::process::exit(3);
-            }
+    app
+}
+
+// Finds --config without clap, which needs the config to build its defaults
+fn config_path() -> Option<PathBuf> {
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
         }
-        batch_no += 1;
     }
+    None
 }
 
-fn send_message(
-    client: &SqsClient,
-    dest_url: &str,
-    message_id: String,
-    body: String,
-    dedup_id: String,
-    group_id: String,
-) {
-    let send_message_input = SendMessageRequest {
-        queue_url: String::from(dest_url),
-        message_body: body,
-        message_deduplication_id: Some(dedup_id),
-        message_group_id: Some(group_id),
+fn with_default<'a>(arg: Arg<'a, 'a>, default: &'a Option<String>) -> Arg<'a, 'a> {
+    match default {
+        Some(default) => arg.default_value(default),
+        None => arg,
+    }
+}
+
+async fn run(matches: &ArgMatches<'_>, output: &Output) -> Result<(), Error> {
+    let credentials = CredentialOptions {
+        source: match matches.value_of("credentials") {
+            Some("profile") => CredentialSource::Profile,
+            Some("env") => CredentialSource::Environment,
+            Some("web-identity") => CredentialSource::WebIdentity,
+            _ => CredentialSource::Chain,
+        },
+        profile: matches.value_of("profile").map(String::from),
+        assume_role: matches
+            .value_of("assume-role-arn")
+            .map(|role_arn| AssumeRole {
+                role_arn: role_arn.to_string(),
+                session_name: matches.value_of("session-name").unwrap().to_string(),
+                external_id: matches.value_of("external-id").map(String::from),
+            }),
+    };
+    let client = sqs_client(
+        region_from_args(matches, "region", "endpoint-url")?,
+        &credentials,
+    )
+    .await?;
+    let retry_policy = RetryPolicy {
+        max_retries: matches.value_of("max-retries").unwrap().parse().unwrap(),
+        timeout: matches
+            .value_of("request-timeout")
+            .map(|v| Duration::from_secs(v.parse().unwrap())),
         ..Default::default()
     };
-
-    match client.send_message(send_message_input).sync() {
-        Ok(result) => {
-            println!(
-                "Sent successfully with sequence number {}",
-                result.sequence_number.unwrap_or(String::from("<unknown>"))
-            );
+    if let Some(matches) = matches.subcommand_matches("send") {
+        let source_client = regional_client(
+            matches,
+            "source-region",
+            "source-endpoint-url",
+            &credentials,
+        )
+        .await?;
+        let source_client = source_client.as_ref().unwrap_or(&client);
+        let dest_client = regional_client(
+            matches,
+            "destination-region",
+            "destination-endpoint-url",
+            &credentials,
+        )
+        .await?;
+        let dest_client = dest_client.as_ref().unwrap_or(&client);
+        let options = ReplayOptions {
+            source_urls: resolve_queue_urls(
+                source_client,
+                matches,
+                "source-queue-url",
+                "source-queue-name",
+                &retry_policy,
+                output,
+            )
+            .await?,
+            dest_urls: resolve_queue_urls(
+                dest_client,
+                matches,
+                "destination-queue-url",
+                "destination-queue-name",
+                &retry_policy,
+                output,
+            )
+            .await?,
+            message_group_id: matches.value_of("message-group-id").map(String::from),
+            group_id_attribute: matches.value_of("group-id-attribute").map(String::from),
+            dedup_id_attribute: matches.value_of("dedup-id-attribute").map(String::from),
+            dedup_strategy: match matches.value_of("dedup-strategy") {
+                Some("message-id") => DedupStrategy::MessageId,
+                Some("body-hash") => DedupStrategy::BodyHash,
+                _ => DedupStrategy::Random,
+            },
+            max_messages: matches.value_of("max-messages").map(|v| v.parse().unwrap()),
+            max_duration: matches
+                .value_of("max-duration")
+                .map(|v| parse_duration(v).unwrap()),
+            batch_size: matches.value_of("batch-size").unwrap().parse().unwrap(),
+            wait_time_seconds: matches.value_of("wait-time").unwrap().parse().unwrap(),
+            max_empty_receives: matches
+                .value_of("max-empty-receives")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            follow: matches.is_present("follow"),
+            wait_for_messages: matches.is_present("wait-for-messages"),
+            startup_timeout: matches
+                .value_of("startup-timeout")
+                .map(|v| parse_duration(v).unwrap()),
+            batch_window: matches
+                .value_of("batch-window")
+                .map(|v| parse_duration(v).unwrap()),
+            visibility_timeout: matches
+                .value_of("visibility-timeout")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            delay_seconds: match matches.value_of("delay-jitter") {
+                Some(range) => Some(parse_delay_range(range).unwrap()),
+                None => matches.value_of("delay-seconds").map(|v| {
+                    let delay = v.parse().unwrap();
+                    delay..=delay
+                }),
+            },
+            filter: matches
+                .value_of("filter-regex")
+                .map(|v| Regex::new(v).unwrap()),
+            attribute_filters: matches
+                .values_of("filter-attribute")
+                .map(|values| values.map(|v| parse_attribute_filter(v).unwrap()).collect())
+                .unwrap_or_default(),
+            message_attribute_names: matches
+                .value_of("attribute-names")
+                .map(parse_names)
+                .unwrap_or_default(),
+            system_attribute_names: matches
+                .value_of("system-attribute-names")
+                .map(parse_names)
+                .unwrap_or_default(),
+            sent_since: matches.value_of("since").map(|v| parse_time(v).unwrap()),
+            sent_until: matches.value_of("until").map(|v| parse_time(v).unwrap()),
+            sample_rate: matches.value_of("sample-rate").map(|v| v.parse().unwrap()),
+            sample_seed: matches.value_of("sample-seed").map(|v| v.parse().unwrap()),
+            on_empty: match matches.value_of("on-empty") {
+                Some("placeholder") => EmptyBodies::Placeholder,
+                Some("fail") => EmptyBodies::Fail,
+                _ => EmptyBodies::Skip,
+            },
+            replacements: matches
+                .values_of("replace")
+                .map(|values| values.map(|v| parse_replacement(v).unwrap()).collect())
+                .unwrap_or_default(),
+            preserve_timestamp: matches.is_present("preserve-timestamp"),
+            copy: matches.is_present("copy"),
+            on_partial_batch: match matches.value_of("on-partial-batch") {
+                Some("delete-none") => PartialBatch::DeleteNone,
+                Some("retry") => PartialBatch::Retry,
+                _ => PartialBatch::DeleteSucceeded,
+            },
+            fifo_retry_strategy: match matches.value_of("fifo-retry-strategy") {
+                Some("wait") => FifoRetryStrategy::Wait,
+                Some("new-id") => FifoRetryStrategy::NewId,
+                _ => FifoRetryStrategy::Send,
+            },
+            continue_on_error: matches.is_present("continue-on-error"),
+            redrive: false,
+            require_fifo: if matches.is_present("require-fifo") {
+                Some(true)
+            } else if matches.is_present("require-standard") {
+                Some(false)
+            } else {
+                None
+            },
+            allow_same_queue: matches.is_present("allow-same-queue"),
+            force: matches.is_present("force"),
+            dry_run: matches.is_present("dry-run"),
+            fail_if_empty: matches.is_present("fail-if-empty"),
+            concurrency: matches.value_of("concurrency").unwrap().parse().unwrap(),
+            concurrency_per_group: matches.is_present("concurrency-per-group"),
+            prefetch: matches.value_of("prefetch").unwrap().parse().unwrap(),
+            preserve_order: matches.is_present("preserve-order"),
+            rate_limit: matches.value_of("rate-limit").map(|v| v.parse().unwrap()),
+            retry_policy,
+            checkpoint_file: matches.value_of("checkpoint-file").map(PathBuf::from),
+            report_file: matches.value_of("report-file").map(PathBuf::from),
+            approve: if !matches.is_present("interactive") {
+                None
+            } else if atty::is(atty::Stream::Stdin) {
+                Some(Box::new(approve_batch))
+            } else {
+                output.emit(Event::Warning {
+                    message: "Not asking before each batch as stdin isn't a terminal",
+                });
+                None
+            },
+            shutdown: Shutdown::on_ctrl_c(),
+        };
+        let summary = replay(source_client, dest_client, options, output).await?;
+        replay_result(&summary)
+    } else if let Some(matches) = matches.subcommand_matches("redrive") {
+        let dlq_url = matches.value_of("dlq-url").unwrap().to_string();
+        let dest_url = match matches.value_of("destination-queue-url") {
+            Some(dest_url) => dest_url.to_string(),
+            None => dead_letter_source_queue(&client, &dlq_url, &retry_policy, output).await?,
+        };
+        let dest_urls = vec![dest_url];
+        // Messages are only deleted from the dead-letter queue once they've been sent
+        let options = ReplayOptions {
+            source_urls: vec![dlq_url],
+            dest_urls,
+            message_group_id: matches.value_of("message-group-id").map(String::from),
+            group_id_attribute: None,
+            dedup_id_attribute: None,
+            dedup_strategy: DedupStrategy::Random,
+            max_messages: matches.value_of("max-messages").map(|v| v.parse().unwrap()),
+            max_duration: matches
+                .value_of("max-duration")
+                .map(|v| parse_duration(v).unwrap()),
+            batch_size: MAX_BATCH_SIZE,
+            wait_time_seconds: 3,
+            max_empty_receives: DEFAULT_EMPTY_RECEIVES,
+            follow: false,
+            wait_for_messages: false,
+            startup_timeout: None,
+            batch_window: None,
+            redrive: true,
+            require_fifo: None,
+            allow_same_queue: false,
+            force: false,
+            visibility_timeout: 30,
+            delay_seconds: None,
+            filter: None,
+            attribute_filters: Vec::new(),
+            message_attribute_names: vec![String::from("All")],
+            system_attribute_names: vec![String::from("All")],
+            sent_since: None,
+            sent_until: None,
+            sample_rate: None,
+            sample_seed: None,
+            on_empty: EmptyBodies::Skip,
+            replacements: Vec::new(),
+            preserve_timestamp: false,
+            copy: false,
+            on_partial_batch: PartialBatch::DeleteSucceeded,
+            fifo_retry_strategy: FifoRetryStrategy::Send,
+            continue_on_error: false,
+            dry_run: matches.is_present("dry-run"),
+            fail_if_empty: matches.is_present("fail-if-empty"),
+            concurrency: 1,
+            concurrency_per_group: false,
+            prefetch: 0,
+            preserve_order: false,
+            rate_limit: None,
+            retry_policy,
+            checkpoint_file: None,
+            report_file: matches.value_of("report-file").map(PathBuf::from),
+            approve: None,
+            shutdown: Shutdown::on_ctrl_c(),
+        };
+        let summary = replay(&client, &client, options, output).await?;
+        replay_result(&summary)
+    } else if let Some(matches) = matches.subcommand_matches("list-queues") {
+        list_queues(
+            &client,
+            matches.value_of("prefix"),
+            matches.value_of("max-results").map(|v| v.parse().unwrap()),
+            &retry_policy,
+            output,
+        )
+        .await
+    } else if let Some(matches) = matches.subcommand_matches("count") {
+        count_messages(
+            &client,
+            matches.value_of("queue-url").unwrap(),
+            &retry_policy,
+            output,
+        )
+        .await
+    } else if let Some(matches) = matches.subcommand_matches("dump") {
+        let options = DumpOptions {
+            queue_url: matches.value_of("queue-url").unwrap().to_string(),
+            output_file: matches.value_of("output-file").unwrap().into(),
+            delete: matches.is_present("delete"),
+            retry_policy,
+        };
+        dump(&client, options, output).await.map(|_| ())
+    } else if let Some(matches) = matches.subcommand_matches("peek") {
+        peek(
+            &client,
+            matches.value_of("queue-url").unwrap(),
+            matches.value_of("count").unwrap().parse().unwrap(),
+            &retry_policy,
+            output,
+        )
+        .await
+        .map(|_| ())
+    } else if let Some(matches) = matches.subcommand_matches("load") {
+        let options = LoadOptions {
+            input_file: matches.value_of("input-file").unwrap().into(),
+            dest_url: matches
+                .value_of("destination-queue-url")
+                .unwrap()
+                .to_string(),
+            message_group_id: matches.value_of("message-group-id").map(String::from),
+            strict: matches.is_present("strict"),
+            retry_policy,
+        };
+        load(&client, options, output).await.map(|_| ())
+    } else if let Some(matches) = matches.subcommand_matches("create-queue") {
+        let options = CreateQueueOptions {
+            queue_name: matches.value_of("queue-name").unwrap().to_string(),
+            fifo: matches.is_present("fifo"),
+            content_based_deduplication: matches.is_present("content-based-dedup"),
+            visibility_timeout: matches
+                .value_of("visibility-timeout")
+                .map(|v| v.parse().unwrap()),
+            retention: matches
+                .value_of("retention")
+                .map(|v| parse_duration(v).unwrap()),
+        };
+        create_queue(&client, &options, &retry_policy, output)
+            .await
+            .map(|_| ())
+    } else if let Some(matches) = matches.subcommand_matches("purge-queue") {
+        let queue_url = matches.value_of("queue-url").unwrap();
+        if matches.is_present("yes") || confirm(&format!("Purge all messages from {}?", queue_url))
+        {
+            purge_queue(&client, queue_url, &retry_policy, output).await
+        } else {
+            output.emit(Event::PurgeCancelled);
+            Ok(())
         }
-        Err(error) => {
-            println!(
-                "Failed to send message ID {} to destination queue: {:?}",
-                message_id, error
-            );
-            std::process::exit(3);
+    } else {
+        Err(Error::MissingSubcommand)
+    }
+}
+
+// Asks a yes/no question on stderr so it doesn't end up in JSON output. Anything other than yes,
+// including no input at all, is a no.
+fn confirm(question: &str) -> bool {
+    matches!(ask(&format!("{} [y/N]", question)).as_str(), "y" | "yes")
+}
+
+// Shows a batch's bodies however verbose the output is, then asks whether to send it
+fn approve_batch(messages: &[Message]) -> Approval {
+    for m in messages {
+        eprintln!(
+            "Message ID {}\n{}\n",
+            m.message_id.as_deref().unwrap_or("<unknown>"),
+            m.body.as_deref().unwrap_or_default()
+        );
+    }
+    match ask(&format!(
+        "Send these {} messages? [y/N/all]",
+        messages.len()
+    ))
+    .as_str()
+    {
+        "y" | "yes" => Approval::Yes,
+        "a" | "all" => Approval::All,
+        _ => Approval::No,
+    }
+}
+
+// The lowercased answer to a question asked on stderr, or nothing if stdin can't be read
+fn ask(question: &str) -> String {
+    eprint!("{} ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => answer.trim().to_lowercase(),
+        Err(_) => String::new(),
+    }
+}
+
+// A replay that finished but left messages behind still needs a non-zero exit code
+fn replay_result(summary: &ReplaySummary) -> Result<(), Error> {
+    if summary.failed > 0 {
+        Err(Error::MessagesFailed(summary.failed, summary.replayed))
+    } else {
+        Ok(())
+    }
+}
+
+// Clap makes sure only one of the URL and name arguments is given, and at least one value for it
+async fn resolve_queue_urls(
+    client: &SqsClient,
+    matches: &ArgMatches<'_>,
+    url_arg: &str,
+    name_arg: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<Vec<String>, Error> {
+    match matches.values_of(url_arg) {
+        Some(urls) => Ok(urls.map(String::from).collect()),
+        None => {
+            let mut urls = Vec::new();
+            for name in matches.values_of(name_arg).unwrap() {
+                urls.push(queue_url(client, name, retry_policy, output).await?);
+            }
+            Ok(urls)
         }
     }
 }
 
-fn delete_message(client: &SqsClient, source_url: &str, receipt_handle: &String) {
-    let delete_message_input = DeleteMessageRequest {
-        queue_url: String::from(source_url),
-        receipt_handle: receipt_handle.to_string(),
+fn parse_attribute_filter(filter: &str) -> Result<(String, String), String> {
+    match filter.find('=') {
+        Some(i) if i > 0 => Ok((filter[..i].to_string(), filter[i + 1..].to_string())),
+        _ => Err(String::from("must be in the form name=value")),
+    }
+}
+
+// Text to find and what to replace it with, split at the first = so only the replacement can
+// contain one
+fn parse_replacement(replacement: &str) -> Result<(String, String), String> {
+    match replacement.find('=') {
+        Some(i) if i > 0 => Ok((
+            replacement[..i].to_string(),
+            replacement[i + 1..].to_string(),
+        )),
+        _ => Err(String::from("must be in the form from=to")),
+    }
+}
+
+// A range of delays like 0-300, within what SQS allows
+fn parse_delay_range(range: &str) -> Result<RangeInclusive<i64>, String> {
+    let invalid = || {
+        format!(
+            "must be a range of seconds like 0-300, between 0 and {}",
+            MAX_DELAY_SECONDS
+        )
     };
-    match client.delete_message(delete_message_input).sync() {
-        Ok(_) => println!("Message deleted from source queue\n"),
-        Err(error) => {
-            println!("Failed to delete message from source queue: {:?}", error);
+    let (min, max) = match range.find('-') {
+        Some(i) => (&range[..i], &range[i + 1..]),
+        None => return Err(invalid()),
+    };
+    match (min.parse::<i64>(), max.parse::<i64>()) {
+        (Ok(min), Ok(max)) if 0 <= min && min <= max && max <= MAX_DELAY_SECONDS => Ok(min..=max),
+        _ => Err(invalid()),
+    }
+}
+
+// An RFC 3339 timestamp, or a duration that long before now
+fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let ago = parse_duration(time).map_err(|_| {
+        String::from(
+            "must be an RFC 3339 time like 2024-05-01T09:00:00Z, or a duration ago like 2h",
+        )
+    })?;
+    chrono::Duration::from_std(ago)
+        .ok()
+        .and_then(|ago| Utc::now().checked_sub_signed(ago))
+        .ok_or_else(|| String::from("is too long ago"))
+}
+
+// Durations like 90s, 30m or 1h30m. A plain number is a number of seconds.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || String::from("must be a duration like 90s, 30m or 1h30m");
+    match duration.parse::<u64>() {
+        Ok(0) => return Err(invalid()),
+        Ok(seconds) => return Ok(Duration::from_secs(seconds)),
+        Err(_) => {}
+    }
+
+    let mut total = 0;
+    let mut digits = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
         }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        total += value * unit;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
     }
+    Ok(Duration::from_secs(total))
+}
+
+// A comma-separated list, ignoring spaces and empty entries
+fn parse_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// A client for just the source or destination, if either has its own region or endpoint
+async fn regional_client(
+    matches: &ArgMatches<'_>,
+    region_arg: &str,
+    endpoint_arg: &str,
+    credentials: &CredentialOptions,
+) -> Result<Option<SqsClient>, Error> {
+    if matches.is_present(region_arg) || matches.is_present(endpoint_arg) {
+        let region = region_from_args(matches, region_arg, endpoint_arg)?;
+        Ok(Some(sqs_client(region, credentials).await?))
+    } else {
+        Ok(None)
+    }
+}
+
+// The region to use, as a custom one with the same name if there's an endpoint for it. Each
+// falls back to the global --region and --endpoint-url.
+fn region_from_args(
+    matches: &ArgMatches,
+    region_arg: &str,
+    endpoint_arg: &str,
+) -> Result<Region, Error> {
+    let region = match matches
+        .value_of(region_arg)
+        .or_else(|| matches.value_of("region"))
+    {
+        Some(name) => {
+            Region::from_str(name).map_err(|error| Error::InvalidRegion(name.to_string(), error))?
+        }
+        None => Region::EuWest1,
+    };
+
+    // AWS_ENDPOINT_URL applies to every AWS service, so the SQS-specific one is preferred
+    let endpoint = matches
+        .value_of(endpoint_arg)
+        .or_else(|| matches.value_of("endpoint-url"))
+        .map(String::from)
+        .or_else(|| env::var("AWS_ENDPOINT_URL").ok())
+        .filter(|endpoint| !endpoint.is_empty());
+    Ok(match endpoint {
+        Some(endpoint) => Region::Custom {
+            name: region.name().to_string(),
+            endpoint,
+        },
+        None => region,
+    })
 }
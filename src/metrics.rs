@@ -0,0 +1,93 @@
+use crate::output::Event;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::time::Instant;
+
+const PREFIX: &str = "sqs_replay";
+
+/// Sends counters and timings to a statsd server as the tool runs. Metrics are sent over UDP
+/// as they happen, so a server that isn't listening never slows a replay down.
+pub struct Metrics {
+    socket: UdpSocket,
+    // When the receive in flight was requested, for timing how long it took
+    receive_started: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    /// Sends metrics to the statsd server at `addr`, e.g. `127.0.0.1:8125`.
+    pub fn connect(addr: &str) -> io::Result<Metrics> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(Metrics {
+            socket,
+            receive_started: Mutex::new(None),
+        })
+    }
+
+    pub(crate) fn record(&self, event: &Event) {
+        if let Event::BatchRequested { .. } = event {
+            *self.receive_started.lock().unwrap() = Some(Instant::now());
+        }
+        let receive_time = match event {
+            Event::BatchReceived { .. } | Event::BatchEmpty { .. } => self
+                .receive_started
+                .lock()
+                .unwrap()
+                .take()
+                .map(|started| started.elapsed()),
+            _ => None,
+        };
+        for line in lines(event, receive_time.map(|time| time.as_millis())) {
+            // Losing the odd metric is better than failing the replay over it
+            let _ = self.socket.send(line.as_bytes());
+        }
+    }
+}
+
+// The statsd lines for an event, in `name:value|type` form
+fn lines(event: &Event, receive_millis: Option<u128>) -> Vec<String> {
+    let count = |name, value: usize| format!("{}.{}:{}|c", PREFIX, name, value);
+    let timing = |name, millis| format!("{}.{}:{}|ms", PREFIX, name, millis);
+
+    let mut lines = match event {
+        Event::BatchReceived {
+            count: received, ..
+        } => vec![count("received", *received)],
+        Event::MessageSent { .. } => vec![count("sent", 1)],
+        Event::MessagesDeleted { count: deleted } => vec![count("deleted", *deleted)],
+        Event::MessageSkipped { .. } => vec![count("skipped", 1)],
+        Event::SendFailed { .. } => vec![count("send_failed", 1)],
+        Event::DeleteFailed { .. } => vec![count("delete_failed", 1)],
+        Event::ReplayFinished { summary, .. } => {
+            vec![timing("replay_time", summary.duration.as_millis())]
+        }
+        _ => Vec::new(),
+    };
+    lines.extend(receive_millis.map(|millis| timing("receive_time", millis)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_messages_and_times_receives() {
+        assert_eq!(
+            lines(&Event::BatchReceived { batch: 1, count: 7 }, Some(120)),
+            vec!["sqs_replay.received:7|c", "sqs_replay.receive_time:120|ms"]
+        );
+        assert_eq!(
+            lines(
+                &Event::MessageSent {
+                    message_id: "1",
+                    sequence_number: None
+                },
+                None
+            ),
+            vec!["sqs_replay.sent:1|c"]
+        );
+        assert!(lines(&Event::NoQueues, None).is_empty());
+    }
+}
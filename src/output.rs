@@ -0,0 +1,1037 @@
+use crate::dump::message_to_json;
+use crate::error::kms_hint;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::replay::{ReplaySummary, StopReason};
+use colored::*;
+use log::{log, log_enabled, Level};
+use rusoto_sqs::Message;
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// Everything the tool reports while it runs. Each event is either rendered as colored text or
+/// as a single line of JSON, depending on the output format.
+#[derive(Debug)]
+pub enum Event<'a> {
+    Queue {
+        queue_url: &'a str,
+    },
+    NoQueues,
+    QueuePurged {
+        queue_url: &'a str,
+    },
+    PurgeCancelled,
+    QueueDepth {
+        queue_url: &'a str,
+        visible: u64,
+        not_visible: u64,
+        delayed: u64,
+    },
+    QueueDetails {
+        queue_url: &'a str,
+        fifo: bool,
+        content_based_deduplication: bool,
+        kms_key_id: Option<&'a str>,
+        visible: u64,
+    },
+    ReplayStarted {
+        source_queue_urls: &'a [String],
+        destination_queue_urls: &'a [String],
+        dry_run: bool,
+        copy: bool,
+        redrive: bool,
+    },
+    BatchRequested {
+        batch: u32,
+        count: i64,
+    },
+    BatchReceived {
+        batch: u32,
+        count: usize,
+    },
+    BatchEmpty {
+        batch: u32,
+    },
+    BatchAlreadySeen {
+        batch: u32,
+    },
+    /// A batch has been sent and deleted, or failed to be. Only the counts for sends and deletes
+    /// are set.
+    BatchFinished {
+        /// The number of the batch whose receive completed it
+        batch: u32,
+        summary: &'a ReplaySummary,
+    },
+    MessageReceived {
+        message_id: &'a str,
+        body: &'a str,
+        /// Left out unless receipt handles are being shown
+        receipt_handle: Option<&'a str>,
+    },
+    MissingReceiptHandle {
+        message_id: &'a str,
+    },
+    MessageSkipped {
+        message_id: &'a str,
+        reason: &'a str,
+    },
+    MessageTransformed {
+        message_id: &'a str,
+        original: &'a str,
+        transformed: &'a str,
+    },
+    WouldReplay {
+        count: usize,
+    },
+    MessageSent {
+        message_id: &'a str,
+        sequence_number: Option<&'a str>,
+    },
+    SendFailed {
+        queue_url: &'a str,
+        message_id: &'a str,
+        code: &'a str,
+        message: Option<&'a str>,
+    },
+    MessagesDeleted {
+        count: usize,
+    },
+    DeleteFailed {
+        message_id: &'a str,
+        code: &'a str,
+        message: Option<&'a str>,
+    },
+    MessagesDumped {
+        count: usize,
+        output_file: &'a str,
+    },
+    DumpFinished {
+        dumped: usize,
+        output_file: &'a str,
+    },
+    LoadFinished {
+        loaded: usize,
+        skipped: usize,
+        failed: usize,
+    },
+    MessagePeeked {
+        message: &'a Message,
+    },
+    PeekFinished {
+        peeked: usize,
+        visibility_timeout: i64,
+    },
+    ReplayFinished {
+        summary: &'a ReplaySummary,
+        requested: Option<usize>,
+        redrive: bool,
+    },
+    Warning {
+        message: &'a str,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+/// Log target for events that are the data a command produces, like queue URLs and message
+/// bodies, rather than a report on its progress. Only these are written to stdout.
+pub(crate) const DATA_TARGET: &str = concat!(env!("CARGO_CRATE_NAME"), "::data");
+
+pub struct Output {
+    format: OutputFormat,
+    progress: Option<Mutex<Progress>>,
+    quiet: bool,
+    progress_json: bool,
+    pretty: bool,
+    max_body_len: Option<usize>,
+    receipt_handles: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Output {
+        Output {
+            format,
+            progress: None,
+            progress_json: false,
+            quiet: false,
+            pretty: false,
+            max_body_len: None,
+            receipt_handles: false,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+
+    /// Only reports errors. Human output is filtered by the logger's level instead, so this only
+    /// affects JSON output and the progress line.
+    pub fn with_quiet(mut self, quiet: bool) -> Output {
+        self.quiet = quiet;
+        if quiet {
+            self.progress = None;
+        }
+        self
+    }
+
+    /// Keeps a status line with the number of messages replayed so far at the bottom of human
+    /// output. It's written to stderr, so it's only worth enabling when stderr is a terminal.
+    pub fn with_progress(mut self, enabled: bool) -> Output {
+        if enabled && !self.quiet && !self.progress_json && self.format == OutputFormat::Human {
+            self.progress = Some(Mutex::new(Progress::default()));
+        }
+        self
+    }
+
+    /// Also writes progress to stderr as one JSON object per line, whatever the output format, so
+    /// a wrapper can follow a replay while stdout is left for the output itself. Each object has a
+    /// `type` and the `version` of its schema, which only changes if fields are removed or change
+    /// meaning:
+    ///
+    /// - `replay_started` with `source_queue_urls`, `destination_queue_urls` and `dry_run`
+    /// - `batch_started` with the `batch` number and how many `messages` it has
+    /// - `batch_completed` with the `batch` number and how many of its messages were `replayed`,
+    ///   `deleted` and `failed`
+    /// - `replay_finished` with the same fields as the `replay_finished` JSON output event
+    ///
+    /// The status line is left out, as it would be mixed in with the progress.
+    pub fn with_progress_json(mut self, progress_json: bool) -> Output {
+        self.progress_json = progress_json;
+        if progress_json {
+            self.progress = None;
+        }
+        self
+    }
+
+    /// Indents message bodies that are JSON in human output. Bodies are left as they are in JSON
+    /// output and when they're sent on.
+    pub fn with_pretty(mut self, pretty: bool) -> Output {
+        self.pretty = pretty;
+        self
+    }
+
+    /// Cuts message bodies in human output off after this many characters. JSON output always has
+    /// the whole body, and the body that's sent on is never touched.
+    pub fn with_max_body_len(mut self, max_body_len: Option<usize>) -> Output {
+        self.max_body_len = max_body_len;
+        self
+    }
+
+    /// Includes each received message's receipt handle, for reproducing deletes by hand. They're
+    /// long and grant access to the message, so they're hidden by default.
+    pub fn with_receipt_handles(mut self, receipt_handles: bool) -> Output {
+        self.receipt_handles = receipt_handles;
+        self
+    }
+
+    /// Also sends metrics for each event, whatever the output format.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Output {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Reports an event. Human output goes through the `log` crate at a level that depends on
+    /// the event, so nothing is shown unless a logger is installed. JSON output is a single
+    /// stream of events on stdout.
+    pub fn emit(&self, event: Event) {
+        let event = match event {
+            Event::MessageReceived {
+                message_id, body, ..
+            } if !self.receipt_handles => Event::MessageReceived {
+                message_id,
+                body,
+                receipt_handle: None,
+            },
+            event => event,
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record(&event);
+        }
+        if self.progress_json {
+            if let Some(progress) = progress_json(&event) {
+                eprintln!("{}", progress);
+            }
+        }
+        match self.format {
+            OutputFormat::Human => {
+                let level = level(&event);
+                let progress = self.progress.as_ref().map(|p| p.lock().unwrap());
+                let target = if is_data(&event) {
+                    DATA_TARGET
+                } else {
+                    module_path!()
+                };
+                if log_enabled!(target: target, level) {
+                    if progress.is_some() {
+                        // Clear the status line so the event is printed in its place
+                        eprint!("\r\x1b[K");
+                    }
+                    log!(target: target, level, "{}", human_message(&event, self.pretty, self.max_body_len));
+                }
+                if let Some(mut progress) = progress {
+                    progress.record(&event);
+                    progress.draw();
+                }
+            }
+            OutputFormat::Json => {
+                if !self.quiet || level(&event) == Level::Error {
+                    println!("{}", to_json(&event))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Progress {
+    // Only set while a replay is running
+    started: Option<Instant>,
+    replayed: usize,
+}
+
+impl Progress {
+    fn record(&mut self, event: &Event) {
+        match event {
+            Event::ReplayStarted { .. } => self.started = Some(Instant::now()),
+            Event::MessageSent { .. } => self.replayed += 1,
+            Event::WouldReplay { count } => self.replayed += count,
+            Event::ReplayFinished { .. } => self.started = None,
+            _ => {}
+        }
+    }
+
+    fn draw(&self) {
+        if let Some(started) = self.started {
+            let elapsed = started.elapsed().as_secs_f64();
+            let rate = if elapsed > 0.0 {
+                self.replayed as f64 / elapsed
+            } else {
+                0.0
+            };
+            eprint!(
+                "\r\x1b[K{}",
+                format!(
+                    "{} messages replayed, {:.1} messages/s, {:.0}s elapsed",
+                    self.replayed, rate, elapsed
+                )
+                .bold()
+            );
+            let _ = io::stderr().flush();
+        }
+    }
+}
+
+const PROGRESS_SCHEMA_VERSION: u32 = 1;
+
+// The stderr progress line for the events `with_progress_json` covers
+fn progress_json(event: &Event) -> Option<Value> {
+    let mut progress = match event {
+        Event::ReplayStarted {
+            source_queue_urls,
+            destination_queue_urls,
+            dry_run,
+            ..
+        } => json!({
+            "type": "replay_started",
+            "source_queue_urls": source_queue_urls,
+            "destination_queue_urls": destination_queue_urls,
+            "dry_run": dry_run,
+        }),
+        Event::BatchReceived { batch, count } => json!({
+            "type": "batch_started",
+            "batch": batch,
+            "messages": count,
+        }),
+        Event::BatchFinished { batch, summary } => json!({
+            "type": "batch_completed",
+            "batch": batch,
+            "replayed": summary.replayed,
+            "deleted": summary.deleted,
+            "failed": summary.failed,
+        }),
+        Event::ReplayFinished { .. } => {
+            let mut progress = to_json(event);
+            progress["type"] = json!("replay_finished");
+            progress.as_object_mut()?.remove("event");
+            progress
+        }
+        _ => return None,
+    };
+    progress["version"] = json!(PROGRESS_SCHEMA_VERSION);
+    Some(progress)
+}
+
+fn is_data(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Queue { .. }
+            | Event::QueueDepth { .. }
+            | Event::MessageReceived { .. }
+            | Event::MessagePeeked { .. }
+    )
+}
+
+// How much detail each event is. Per-message events are only shown with --verbose.
+fn level(event: &Event) -> Level {
+    match event {
+        Event::Error { .. } => Level::Error,
+        Event::Warning { .. }
+        | Event::MissingReceiptHandle { .. }
+        | Event::SendFailed { .. }
+        | Event::DeleteFailed { .. } => Level::Warn,
+        Event::BatchRequested { .. }
+        | Event::BatchFinished { .. }
+        | Event::MessageSkipped { .. }
+        | Event::MessageSent { .. } => Level::Debug,
+        Event::MessageReceived { .. } | Event::MessageTransformed { .. } => Level::Trace,
+        _ => Level::Info,
+    }
+}
+
+// Indents a body that's JSON if asked to, then cuts it off if it's too long to print
+fn display_body(body: &str, pretty: bool, max_len: Option<usize>) -> String {
+    let json = if pretty {
+        serde_json::from_str::<Value>(body).ok()
+    } else {
+        None
+    };
+    let body = match json {
+        Some(json) => serde_json::to_string_pretty(&json).unwrap(),
+        None => body.to_string(),
+    };
+    match max_len.and_then(|max_len| body.char_indices().nth(max_len)) {
+        Some((end, _)) => format!("{}…(truncated)", &body[..end]),
+        None => body,
+    }
+}
+
+fn human_message(event: &Event, pretty: bool, max_body_len: Option<usize>) -> String {
+    match event {
+        Event::Queue { queue_url } => queue_url.to_string(),
+        Event::NoQueues => String::from("No queues"),
+        Event::QueuePurged { queue_url } => format!(
+            "{} {}\nSQS can take up to 60 seconds to delete every message, and some may still be received until then",
+            "Purged".green(),
+            queue_url
+        ),
+        Event::PurgeCancelled => String::from("Purge cancelled"),
+        Event::QueueDepth {
+            queue_url,
+            visible,
+            not_visible,
+            delayed,
+        } => format!(
+            "{}\n {} {}\n {} {}\n {} {}",
+            queue_url,
+            "  Available".green(),
+            visible,
+            "  In flight".green(),
+            not_visible,
+            "    Delayed".green(),
+            delayed
+        ),
+        Event::QueueDetails {
+            queue_url,
+            fifo,
+            content_based_deduplication,
+            kms_key_id,
+            visible,
+        } => format!(
+            "{}\n {} {}\n {} {}\n {} {}\n {} {}\n",
+            queue_url,
+            "                       Type".green(),
+            if *fifo { "FIFO" } else { "Standard" },
+            "Content-based deduplication".green(),
+            if *content_based_deduplication {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            "                 Encryption".green(),
+            match kms_key_id {
+                Some(key_id) => format!("SSE-KMS ({})", key_id),
+                None => String::from("SSE-SQS or none"),
+            },
+            "       Available (approx.)".green(),
+            visible
+        ),
+        Event::ReplayStarted {
+            source_queue_urls,
+            destination_queue_urls,
+            dry_run,
+            copy,
+            redrive,
+        } => {
+            let mut message = if *redrive {
+                format!(
+                    " {} {}\n {} {}\n",
+                    "Dead-letter queue URL".green(),
+                    source_queue_urls.join(", "),
+                    "   Redrive queue URL".green(),
+                    destination_queue_urls.join(", ")
+                )
+            } else {
+                format!(
+                    " {} {}\n {} {}\n",
+                    "     Source queue URL".green(),
+                    source_queue_urls.join(", "),
+                    "Destination queue URL".green(),
+                    destination_queue_urls.join(", ")
+                )
+            };
+            if *dry_run {
+                message += &format!(
+                    " {}\n",
+                    "Dry run: no messages will be sent or deleted".yellow()
+                );
+            } else if *copy {
+                message += &format!(
+                    " {}\n",
+                    "Copy mode: messages will be left in the source queue".yellow()
+                );
+            }
+            message
+        }
+        Event::BatchRequested { batch, count } => {
+            format!("Requesting {} messages in batch {}", count, batch)
+                .cyan()
+                .to_string()
+        }
+        Event::BatchReceived { count, .. } => {
+            format!("{} messages received\n", count).cyan().to_string()
+        }
+        Event::BatchEmpty { batch } => format!("No messages received in batch {}\n", batch)
+            .cyan()
+            .to_string(),
+        Event::BatchAlreadySeen { .. } => "All messages in batch already seen".cyan().to_string(),
+        Event::BatchFinished { batch, summary } => format!(
+            "Batch {} finished: sent {}, deleted {}, failed {}",
+            batch, summary.replayed, summary.deleted, summary.failed
+        ),
+        Event::MessageReceived {
+            message_id,
+            body,
+            receipt_handle,
+        } => {
+            let body = display_body(body, pretty, max_body_len);
+            let receipt_handle = match receipt_handle {
+                Some(receipt_handle) => format!("{} {}\n", "Receipt handle".green(), receipt_handle),
+                None => String::new(),
+            };
+            format!(
+                "{} {}\n{}{}\n",
+                "Message ID".green(),
+                message_id,
+                receipt_handle,
+                body
+            )
+        }
+        Event::MissingReceiptHandle { message_id } => format!(
+            "Didn't receive receipt handle for Message ID: {} Continuing to next message...",
+            message_id
+        ),
+        Event::MessageSkipped { message_id, reason } => {
+            format!("Skipping message ID {} as it {}", message_id, reason)
+        }
+        Event::MessageTransformed {
+            message_id,
+            original,
+            transformed,
+        } => format!(
+            "Transformed message ID {}\n{} {}\n{} {}",
+            message_id,
+            "Original".green(),
+            display_body(original, false, max_body_len),
+            "Replaced".green(),
+            display_body(transformed, false, max_body_len)
+        ),
+        Event::WouldReplay { count } => format!(
+            "Would send {} messages to destination queue and delete them from source queue\n",
+            count
+        ),
+        Event::MessageSent {
+            message_id,
+            sequence_number,
+        } => format!(
+            "Sent message ID {} successfully with sequence number {}",
+            message_id,
+            sequence_number.unwrap_or("<unknown>")
+        ),
+        Event::SendFailed {
+            queue_url,
+            message_id,
+            code,
+            message,
+        } => {
+            let mut line = format!(
+                "{} {} to {}: {} {}",
+                "Failed to send message ID".red(),
+                message_id,
+                queue_url,
+                code,
+                message.unwrap_or("")
+            );
+            if let Some(hint) = kms_hint(code) {
+                line.push_str(&format!(" ({})", hint));
+            }
+            line
+        }
+        Event::MessagesDeleted { count } => {
+            format!("{} messages deleted from source queue\n", count)
+        }
+        Event::DeleteFailed {
+            message_id,
+            code,
+            message,
+        } => format!(
+            "{} {} from source queue: {} {}",
+            "Failed to delete message ID".red(),
+            message_id,
+            code,
+            message.unwrap_or("")
+        ),
+        Event::MessagesDumped { count, output_file } => {
+            format!("{} messages written to {}\n", count, output_file)
+        }
+        Event::DumpFinished {
+            dumped,
+            output_file,
+        } => format!("Dumped {} messages to {}", dumped, output_file)
+            .green()
+            .to_string(),
+        Event::LoadFinished {
+            loaded,
+            skipped,
+            failed,
+        } => {
+            let message = format!("Loaded {} messages, skipped {} lines", loaded, skipped);
+            if *failed > 0 {
+                format!("{}, failed to send {} messages", message, failed)
+                    .yellow()
+                    .to_string()
+            } else {
+                message.green().to_string()
+            }
+        }
+        Event::MessagePeeked { message } => {
+            let body = display_body(
+                message.body.as_deref().unwrap_or_default(),
+                pretty,
+                max_body_len,
+            );
+            let mut attributes: Vec<String> = message
+                .attributes
+                .iter()
+                .flatten()
+                .map(|(name, value)| format!(" {} {}\n", name.green(), value))
+                .collect();
+            attributes.sort();
+            let mut message_attributes: Vec<String> = message
+                .message_attributes
+                .iter()
+                .flatten()
+                .map(|(name, value)| {
+                    let value = match (&value.string_value, &value.binary_value) {
+                        (Some(string_value), _) => string_value.clone(),
+                        (None, Some(binary_value)) => base64::encode(&binary_value[..]),
+                        (None, None) => String::new(),
+                    };
+                    format!(" {} {}\n", name.cyan(), value)
+                })
+                .collect();
+            message_attributes.sort();
+            format!(
+                "{} {}\n{}{}{}\n",
+                "Message ID".green(),
+                message.message_id.as_deref().unwrap_or("<unknown>"),
+                attributes.concat(),
+                message_attributes.concat(),
+                body
+            )
+        }
+        Event::PeekFinished {
+            peeked,
+            visibility_timeout,
+        } => format!(
+            "Peeked at {} messages, which will be visible again in {}s",
+            peeked, visibility_timeout
+        )
+        .green()
+        .to_string(),
+        Event::ReplayFinished {
+            summary,
+            requested,
+            redrive,
+        } => {
+            let verb = if *redrive { "Redrove" } else { "Replayed" };
+            let replayed = match requested {
+                Some(requested) => format!(
+                    "{} {} of {} requested messages",
+                    verb, summary.replayed, requested
+                ),
+                None => format!("{} {} messages", verb, summary.replayed),
+            };
+            format!(
+                "{}\nReceived {}, sent {}, deleted {}, skipped {}, failed {} in {:.1}s ({:.1} messages/s)\nMade {} requests: {} receives, {} sends, {} deletes{}{}\nStopped because {}",
+                replayed.green(),
+                summary.received,
+                summary.replayed,
+                summary.deleted,
+                summary.skipped,
+                summary.failed,
+                summary.duration.as_secs_f64(),
+                summary.messages_per_second(),
+                summary.requests(),
+                summary.receive_requests,
+                summary.send_requests,
+                summary.delete_requests,
+                if summary.expired > 0 {
+                    format!(
+                        "\n{} of the failed messages had expired receipt handles and will likely be replayed again",
+                        summary.expired
+                    )
+                    .yellow()
+                    .to_string()
+                } else {
+                    String::new()
+                },
+                if summary.deduplicated > 0 {
+                    format!(
+                        "\n{} of the sent messages reused a deduplication ID from the last five minutes and may have been dropped by SQS",
+                        summary.deduplicated
+                    )
+                    .yellow()
+                    .to_string()
+                } else {
+                    String::new()
+                },
+                match summary.stop_reason {
+                    StopReason::Drained => "the queue was drained",
+                    StopReason::MessageLimit => "the message limit was reached",
+                    StopReason::TimeLimit => "the time limit was reached",
+                    StopReason::Interrupted => "it was interrupted",
+                    StopReason::Failed => "a message failed",
+                }
+            )
+        }
+        Event::Warning { message } => message.yellow().to_string(),
+        Event::Error { message } => message.to_string(),
+    }
+}
+
+fn to_json(event: &Event) -> Value {
+    match event {
+        Event::Queue { queue_url } => json!({ "event": "queue", "queue_url": queue_url }),
+        Event::NoQueues => json!({ "event": "no_queues" }),
+        Event::QueuePurged { queue_url } => {
+            json!({ "event": "queue_purged", "queue_url": queue_url })
+        }
+        Event::PurgeCancelled => json!({ "event": "purge_cancelled" }),
+        Event::QueueDepth {
+            queue_url,
+            visible,
+            not_visible,
+            delayed,
+        } => json!({
+            "event": "queue_depth",
+            "queue_url": queue_url,
+            "visible": visible,
+            "not_visible": not_visible,
+            "delayed": delayed,
+        }),
+        Event::QueueDetails {
+            queue_url,
+            fifo,
+            content_based_deduplication,
+            kms_key_id,
+            visible,
+        } => json!({
+            "event": "queue_details",
+            "queue_url": queue_url,
+            "fifo": fifo,
+            "content_based_deduplication": content_based_deduplication,
+            "kms_key_id": kms_key_id,
+            "visible": visible,
+        }),
+        Event::ReplayStarted {
+            source_queue_urls,
+            destination_queue_urls,
+            dry_run,
+            copy,
+            redrive,
+        } => json!({
+            "event": "replay_started",
+            "source_queue_urls": source_queue_urls,
+            "destination_queue_urls": destination_queue_urls,
+            "dry_run": dry_run,
+            "copy": copy,
+            "redrive": redrive,
+        }),
+        Event::BatchRequested { batch, count } => {
+            json!({ "event": "batch_requested", "batch": batch, "count": count })
+        }
+        Event::BatchReceived { batch, count } => {
+            json!({ "event": "batch_received", "batch": batch, "count": count })
+        }
+        Event::BatchFinished { batch, summary } => json!({
+            "event": "batch_finished",
+            "batch": batch,
+            "replayed": summary.replayed,
+            "deleted": summary.deleted,
+            "failed": summary.failed,
+            "expired": summary.expired,
+        }),
+        Event::BatchEmpty { batch } => json!({ "event": "batch_empty", "batch": batch }),
+        Event::BatchAlreadySeen { batch } => {
+            json!({ "event": "batch_already_seen", "batch": batch })
+        }
+        Event::MessageReceived {
+            message_id,
+            body,
+            receipt_handle: None,
+        } => json!({
+            "event": "message_received",
+            "message_id": message_id,
+            "body": body,
+        }),
+        Event::MessageReceived {
+            message_id,
+            body,
+            receipt_handle: Some(receipt_handle),
+        } => json!({
+            "event": "message_received",
+            "message_id": message_id,
+            "body": body,
+            "receipt_handle": receipt_handle,
+        }),
+        Event::MissingReceiptHandle { message_id } => json!({
+            "event": "missing_receipt_handle",
+            "message_id": message_id,
+        }),
+        Event::MessageSkipped { message_id, reason } => json!({
+            "event": "message_skipped",
+            "message_id": message_id,
+            "reason": reason,
+        }),
+        Event::MessageTransformed {
+            message_id,
+            original,
+            transformed,
+        } => json!({
+            "event": "message_transformed",
+            "message_id": message_id,
+            "original": original,
+            "transformed": transformed,
+        }),
+        Event::WouldReplay { count } => json!({ "event": "would_replay", "count": count }),
+        Event::MessageSent {
+            message_id,
+            sequence_number,
+        } => json!({
+            "event": "message_sent",
+            "message_id": message_id,
+            "sequence_number": sequence_number,
+        }),
+        Event::SendFailed {
+            queue_url,
+            message_id,
+            code,
+            message,
+        } => json!({
+            "event": "send_failed",
+            "queue_url": queue_url,
+            "message_id": message_id,
+            "code": code,
+            "message": message,
+            "hint": kms_hint(code),
+        }),
+        Event::MessagesDeleted { count } => {
+            json!({ "event": "messages_deleted", "count": count })
+        }
+        Event::DeleteFailed {
+            message_id,
+            code,
+            message,
+        } => json!({
+            "event": "delete_failed",
+            "message_id": message_id,
+            "code": code,
+            "message": message,
+        }),
+        Event::MessagesDumped { count, output_file } => json!({
+            "event": "messages_dumped",
+            "count": count,
+            "output_file": output_file,
+        }),
+        Event::DumpFinished {
+            dumped,
+            output_file,
+        } => json!({
+            "event": "dump_finished",
+            "dumped": dumped,
+            "output_file": output_file,
+        }),
+        Event::LoadFinished {
+            loaded,
+            skipped,
+            failed,
+        } => json!({
+            "event": "load_finished",
+            "loaded": loaded,
+            "skipped": skipped,
+            "failed": failed,
+        }),
+        Event::MessagePeeked { message } => {
+            let mut json = message_to_json(message);
+            json["event"] = json!("message_peeked");
+            json
+        }
+        Event::PeekFinished {
+            peeked,
+            visibility_timeout,
+        } => json!({
+            "event": "peek_finished",
+            "peeked": peeked,
+            "visibility_timeout": visibility_timeout,
+        }),
+        Event::ReplayFinished {
+            summary,
+            requested,
+            redrive,
+        } => json!({
+            "event": "replay_finished",
+            "redrive": redrive,
+            "received": summary.received,
+            "replayed": summary.replayed,
+            "deleted": summary.deleted,
+            "skipped": summary.skipped,
+            "failed": summary.failed,
+            "expired": summary.expired,
+            "deduplicated": summary.deduplicated,
+            "duration_ms": summary.duration.as_millis() as u64,
+            "messages_per_second": summary.messages_per_second(),
+            "requests": {
+                "receive": summary.receive_requests,
+                "send": summary.send_requests,
+                "delete": summary.delete_requests,
+            },
+            "requested": requested,
+            "stop_reason": match summary.stop_reason {
+                StopReason::Drained => "drained",
+                StopReason::MessageLimit => "message_limit",
+                StopReason::TimeLimit => "time_limit",
+                StopReason::Interrupted => "interrupted",
+                StopReason::Failed => "failed",
+            },
+        }),
+        Event::Warning { message } => json!({ "event": "warning", "message": message }),
+        Event::Error { message } => json!({ "event": "error", "message": message }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn cuts_off_long_bodies_on_a_character_boundary() {
+        assert_eq!(display_body("short", false, Some(10)), "short");
+        assert_eq!(display_body("exactly10!", false, Some(10)), "exactly10!");
+        assert_eq!(
+            display_body("a much longer body", false, Some(6)),
+            "a much…(truncated)"
+        );
+        // Cutting at byte 4 rather than character 4 would split é in two
+        assert_eq!(
+            display_body("café au lait", false, Some(4)),
+            "café…(truncated)"
+        );
+        assert_eq!(display_body("caféine", false, Some(3)), "caf…(truncated)");
+        assert_eq!(
+            display_body("a much longer body", false, None),
+            "a much longer body"
+        );
+    }
+
+    #[test]
+    fn writes_progress_in_the_versioned_schema() {
+        let sources = vec![String::from(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/source",
+        )];
+        let destinations = vec![String::from(
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/destination",
+        )];
+        assert_eq!(
+            progress_json(&Event::ReplayStarted {
+                source_queue_urls: &sources,
+                destination_queue_urls: &destinations,
+                dry_run: false,
+                copy: false,
+                redrive: false,
+            }),
+            Some(json!({
+                "type": "replay_started",
+                "source_queue_urls": sources,
+                "destination_queue_urls": destinations,
+                "dry_run": false,
+                "version": 1,
+            }))
+        );
+        assert_eq!(
+            progress_json(&Event::BatchReceived {
+                batch: 2,
+                count: 10
+            }),
+            Some(json!({ "type": "batch_started", "batch": 2, "messages": 10, "version": 1 }))
+        );
+
+        let summary = ReplaySummary {
+            received: 10,
+            replayed: 9,
+            deleted: 8,
+            failed: 1,
+            duration: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            progress_json(&Event::BatchFinished {
+                batch: 3,
+                summary: &summary
+            }),
+            Some(json!({
+                "type": "batch_completed",
+                "batch": 3,
+                "replayed": 9,
+                "deleted": 8,
+                "failed": 1,
+                "version": 1,
+            }))
+        );
+
+        let finished = progress_json(&Event::ReplayFinished {
+            summary: &summary,
+            requested: None,
+            redrive: false,
+        })
+        .unwrap();
+        assert_eq!(finished["type"], "replay_finished");
+        assert_eq!(finished["version"], 1);
+        assert_eq!(finished["replayed"], 9);
+        assert_eq!(finished["stop_reason"], "drained");
+        assert!(finished.get("event").is_none());
+
+        assert_eq!(progress_json(&Event::BatchEmpty { batch: 3 }), None);
+    }
+}
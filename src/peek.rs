@@ -0,0 +1,73 @@
+use crate::error::Error;
+use crate::output::{Event, Output};
+use crate::replay::MAX_BATCH_SIZE;
+use crate::retry::RetryPolicy;
+use rusoto_sqs::{ReceiveMessageRequest, Sqs};
+use std::collections::HashSet;
+use std::time::Duration;
+
+// Long enough that later receives don't get the same messages again, but short enough that
+// they're soon back for the queue's consumers
+const PEEK_VISIBILITY_TIMEOUT: i64 = 5;
+
+const PEEK_WAIT_TIME_SECONDS: i64 = 1;
+
+/// Prints up to `count` messages from the queue with their attributes, returning the number
+/// printed. Nothing is deleted, so they become visible again once the short visibility timeout
+/// expires.
+pub async fn peek(
+    client: &dyn Sqs,
+    queue_url: &str,
+    count: usize,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<usize, Error> {
+    let mut seen_message_ids = HashSet::new();
+    let mut batch_no = 1;
+    while seen_message_ids.len() < count {
+        let remaining = (count - seen_message_ids.len()).min(MAX_BATCH_SIZE as usize);
+        output.emit(Event::BatchRequested {
+            batch: batch_no,
+            count: remaining as i64,
+        });
+        let receive_message_input = ReceiveMessageRequest {
+            queue_url: String::from(queue_url),
+            max_number_of_messages: Some(remaining as i64),
+            wait_time_seconds: Some(PEEK_WAIT_TIME_SECONDS),
+            visibility_timeout: Some(PEEK_VISIBILITY_TIMEOUT),
+            attribute_names: Some(vec![String::from("All")]),
+            message_attribute_names: Some(vec![String::from("All")]),
+            ..Default::default()
+        };
+        let result = retry_policy
+            .allowing(Duration::from_secs(PEEK_WAIT_TIME_SECONDS as u64))
+            .run(output, || {
+                client.receive_message(receive_message_input.clone())
+            })
+            .await
+            .map_err(|error| Error::ReceiveMessage(String::from(queue_url), Box::new(error)))?;
+
+        // An empty receive, or one of messages already printed, means there's nothing more to see
+        // until they're visible again
+        let mut new_messages = 0;
+        for m in result.messages.unwrap_or_default().iter() {
+            if let Some(message_id) = &m.message_id {
+                if seen_message_ids.insert(message_id.clone()) {
+                    new_messages += 1;
+                    output.emit(Event::MessagePeeked { message: m });
+                }
+            }
+        }
+        if new_messages == 0 {
+            output.emit(Event::BatchEmpty { batch: batch_no });
+            break;
+        }
+        batch_no += 1;
+    }
+
+    output.emit(Event::PeekFinished {
+        peeked: seen_message_ids.len(),
+        visibility_timeout: PEEK_VISIBILITY_TIMEOUT,
+    });
+    Ok(seen_message_ids.len())
+}
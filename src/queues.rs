@@ -0,0 +1,384 @@
+use crate::error::Error;
+use crate::output::{Event, Output};
+use crate::retry::RetryPolicy;
+use rusoto_sqs::{
+    CreateQueueRequest, GetQueueAttributesRequest, GetQueueUrlRequest,
+    ListDeadLetterSourceQueuesRequest, ListQueuesRequest, PurgeQueueRequest, Sqs,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// The KmsMasterKeyId of queues encrypted with the key AWS manages for SQS
+const AWS_MANAGED_KMS_KEY: &str = "alias/aws/sqs";
+
+/// SQS silently truncates the list of queues at this many results
+pub const MAX_LIST_RESULTS: usize = 1000;
+
+/// The longest SQS will keep a message, 14 days
+pub const MAX_RETENTION_SECONDS: u64 = 14 * 24 * 60 * 60;
+
+/// Reports the URL of every queue visible to the client, optionally only those whose names start
+/// with `prefix` and at most `max_results` of them.
+///
+/// The version of the SQS API used here predates `NextToken`, so there's no way to page past the
+/// first 1000 queues. A warning is reported when the list may have been truncated.
+pub async fn list_queues(
+    client: &dyn Sqs,
+    prefix: Option<&str>,
+    max_results: Option<usize>,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<(), Error> {
+    let list_input = ListQueuesRequest {
+        queue_name_prefix: prefix.map(String::from),
+    };
+
+    let queues = retry_policy
+        .run(output, || client.list_queues(list_input.clone()))
+        .await?;
+    match queues.queue_urls {
+        Some(urls) => {
+            let limit = max_results.unwrap_or(MAX_LIST_RESULTS);
+            for u in urls.iter().take(limit) {
+                output.emit(Event::Queue { queue_url: u });
+            }
+            if limit >= MAX_LIST_RESULTS && urls.len() >= MAX_LIST_RESULTS {
+                output.emit(Event::Warning {
+                    message: &format!(
+                        "SQS only lists the first {} queues, use --prefix to narrow the list",
+                        MAX_LIST_RESULTS
+                    ),
+                });
+            }
+        }
+        None => output.emit(Event::NoQueues),
+    }
+    Ok(())
+}
+
+/// Checks that a queue URL looks like `https://sqs.<region>.amazonaws.com/<account ID>/<name>`,
+/// so a mistyped one fails straight away rather than as an error from SQS part way through.
+/// Other hosts, like LocalStack, only need a path ending in a valid queue name.
+pub fn validate_queue_url(url: &str) -> Result<(), String> {
+    let example = "like https://sqs.eu-west-1.amazonaws.com/123456789012/my-queue";
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| format!("must start with http:// or https://, {}", example))?;
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = host.split(':').next().unwrap_or_default();
+    if host.is_empty() {
+        return Err(format!("is missing the host, {}", example));
+    }
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let name = match segments.last() {
+        Some(name) => name,
+        None => return Err(format!("must end with the queue name, {}", example)),
+    };
+    // The .fifo suffix counts towards the 80 characters
+    let valid_name = name.strip_suffix(".fifo").unwrap_or(name);
+    if valid_name.is_empty()
+        || name.len() > 80
+        || !valid_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "{} isn't a valid queue name, which is up to 80 letters, numbers, hyphens and underscores",
+            name
+        ));
+    }
+    if host.ends_with(".amazonaws.com") || host.ends_with(".amazonaws.com.cn") {
+        match segments.as_slice() {
+            [account, _] if account.len() == 12 && account.chars().all(|c| c.is_ascii_digit()) => {}
+            [account, _] => {
+                return Err(format!("{} isn't a 12 digit AWS account ID", account));
+            }
+            _ => {
+                return Err(format!(
+                    "must have the account ID and queue name in its path, {}",
+                    example
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the URL of the queue called `name` in the client's account and region.
+pub async fn queue_url(
+    client: &dyn Sqs,
+    name: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<String, Error> {
+    let url_input = GetQueueUrlRequest {
+        queue_name: String::from(name),
+        queue_owner_aws_account_id: None,
+    };
+
+    let result = retry_policy
+        .run(output, || client.get_queue_url(url_input.clone()))
+        .await
+        .map_err(|error| Error::GetQueueUrl(String::from(name), Box::new(error)))?;
+    Ok(result.queue_url.unwrap_or_default())
+}
+
+/// The settings for a new queue. Anything left unset gets SQS's default.
+#[derive(Clone, Debug, Default)]
+pub struct CreateQueueOptions {
+    /// `.fifo` is appended for FIFO queues that don't already end with it, and a name that
+    /// does end with it always makes a FIFO queue
+    pub queue_name: String,
+    pub fifo: bool,
+    pub content_based_deduplication: bool,
+    pub visibility_timeout: Option<i64>,
+    /// How long SQS keeps a message, between a minute and 14 days
+    pub retention: Option<Duration>,
+}
+
+/// Creates a queue and reports its URL. SQS returns the existing queue's URL if there's already
+/// one with the same name and attributes, so this is safe to run more than once.
+pub async fn create_queue(
+    client: &dyn Sqs,
+    options: &CreateQueueOptions,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<String, Error> {
+    let mut queue_name = options.queue_name.clone();
+    let mut attributes = HashMap::new();
+    if options.fifo || queue_name.ends_with(".fifo") {
+        if !queue_name.ends_with(".fifo") {
+            queue_name.push_str(".fifo");
+        }
+        attributes.insert(String::from("FifoQueue"), String::from("true"));
+    }
+    if options.content_based_deduplication {
+        attributes.insert(
+            String::from("ContentBasedDeduplication"),
+            String::from("true"),
+        );
+    }
+    if let Some(timeout) = options.visibility_timeout {
+        attributes.insert(String::from("VisibilityTimeout"), timeout.to_string());
+    }
+    if let Some(retention) = options.retention {
+        attributes.insert(
+            String::from("MessageRetentionPeriod"),
+            retention.as_secs().to_string(),
+        );
+    }
+    let create_input = CreateQueueRequest {
+        queue_name: queue_name.clone(),
+        attributes: Some(attributes).filter(|attributes| !attributes.is_empty()),
+        ..Default::default()
+    };
+
+    let result = retry_policy
+        .run(output, || client.create_queue(create_input.clone()))
+        .await
+        .map_err(|error| Error::CreateQueue(queue_name, Box::new(error)))?;
+    let queue_url = result.queue_url.unwrap_or_default();
+    output.emit(Event::Queue {
+        queue_url: &queue_url,
+    });
+    Ok(queue_url)
+}
+
+/// Deletes every message in the queue. SQS carries on deleting in the background for up to a
+/// minute after the request returns.
+pub async fn purge_queue(
+    client: &dyn Sqs,
+    queue_url: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<(), Error> {
+    let purge_input = PurgeQueueRequest {
+        queue_url: String::from(queue_url),
+    };
+
+    retry_policy
+        .run(output, || client.purge_queue(purge_input.clone()))
+        .await
+        .map_err(|error| Error::PurgeQueue(String::from(queue_url), Box::new(error)))?;
+    output.emit(Event::QueuePurged { queue_url });
+    Ok(())
+}
+
+/// Reports roughly how many messages are waiting in the queue, being processed and delayed.
+/// Nothing in the queue is changed.
+pub async fn count_messages(
+    client: &dyn Sqs,
+    queue_url: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<(), Error> {
+    let attributes_input = GetQueueAttributesRequest {
+        queue_url: String::from(queue_url),
+        attribute_names: Some(vec![
+            String::from("ApproximateNumberOfMessages"),
+            String::from("ApproximateNumberOfMessagesNotVisible"),
+            String::from("ApproximateNumberOfMessagesDelayed"),
+        ]),
+    };
+
+    let result = retry_policy
+        .run(output, || {
+            client.get_queue_attributes(attributes_input.clone())
+        })
+        .await
+        .map_err(|error| Error::GetQueueAttributes(String::from(queue_url), Box::new(error)))?;
+    let attributes = result.attributes.unwrap_or_default();
+    output.emit(Event::QueueDepth {
+        queue_url,
+        visible: count_attribute(&attributes, "ApproximateNumberOfMessages"),
+        not_visible: count_attribute(&attributes, "ApproximateNumberOfMessagesNotVisible"),
+        delayed: count_attribute(&attributes, "ApproximateNumberOfMessagesDelayed"),
+    });
+    Ok(())
+}
+
+// SQS returns every attribute as a string
+fn count_attribute(attributes: &HashMap<String, String>, name: &str) -> u64 {
+    attributes
+        .get(name)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
+
+/// What a replay needs to know about a queue before it starts.
+pub(crate) struct QueueDetails {
+    pub(crate) fifo: bool,
+    pub(crate) content_based_deduplication: bool,
+    /// The KMS key messages are encrypted with, if the queue uses SSE-KMS rather than SSE-SQS
+    pub(crate) kms_key_id: Option<String>,
+    /// Roughly how many messages are available to receive
+    pub(crate) visible: u64,
+}
+
+/// Reports whether the queue is FIFO, whether it deduplicates messages by their content and
+/// roughly how many messages are in it, as a check on what's about to be replayed.
+pub(crate) async fn queue_details(
+    client: &dyn Sqs,
+    queue_url: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<QueueDetails, Error> {
+    let attributes_input = GetQueueAttributesRequest {
+        queue_url: String::from(queue_url),
+        attribute_names: Some(vec![
+            String::from("FifoQueue"),
+            String::from("ContentBasedDeduplication"),
+            String::from("ApproximateNumberOfMessages"),
+            String::from("KmsMasterKeyId"),
+        ]),
+    };
+
+    let result = retry_policy
+        .run(output, || {
+            client.get_queue_attributes(attributes_input.clone())
+        })
+        .await
+        .map_err(|error| Error::GetQueueAttributes(String::from(queue_url), Box::new(error)))?;
+    let attributes = result.attributes.unwrap_or_default();
+    // Both flags are left out of the response for standard queues
+    let details = QueueDetails {
+        fifo: attributes
+            .get("FifoQueue")
+            .is_some_and(|fifo| fifo == "true"),
+        content_based_deduplication: attributes
+            .get("ContentBasedDeduplication")
+            .is_some_and(|enabled| enabled == "true"),
+        kms_key_id: attributes.get("KmsMasterKeyId").cloned(),
+        visible: count_attribute(&attributes, "ApproximateNumberOfMessages"),
+    };
+    output.emit(Event::QueueDetails {
+        queue_url,
+        fifo: details.fifo,
+        content_based_deduplication: details.content_based_deduplication,
+        kms_key_id: details.kms_key_id.as_deref(),
+        visible: details.visible,
+    });
+    Ok(details)
+}
+
+/// Warns that sending to a queue encrypted with a customer managed KMS key needs permission to
+/// use the key, which isn't checked until the first send fails. The AWS managed key allows anyone
+/// in the account who can use SQS, so it's only a concern for the others.
+pub(crate) fn check_kms_key(details: &QueueDetails, queue_url: &str, output: &Output) {
+    match details.kms_key_id.as_deref() {
+        Some(key_id) if key_id != AWS_MANAGED_KMS_KEY => output.emit(Event::Warning {
+            message: &format!(
+                "{} is encrypted with KMS key {}, so sending to it needs kms:GenerateDataKey and kms:Decrypt on the key, which the key policy must allow",
+                queue_url, key_id
+            ),
+        }),
+        _ => {}
+    }
+}
+
+/// Finds the queue that moves its failed messages to the dead-letter queue, which is where a
+/// redrive sends them back to. It's an error unless exactly one queue uses the dead-letter queue.
+pub async fn dead_letter_source_queue(
+    client: &dyn Sqs,
+    dlq_url: &str,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<String, Error> {
+    let list_input = ListDeadLetterSourceQueuesRequest {
+        queue_url: String::from(dlq_url),
+    };
+
+    let mut result = retry_policy
+        .run(output, || {
+            client.list_dead_letter_source_queues(list_input.clone())
+        })
+        .await
+        .map_err(|error| {
+            Error::ListDeadLetterSourceQueues(String::from(dlq_url), Box::new(error))
+        })?;
+    match result.queue_urls.len() {
+        1 => Ok(result.queue_urls.remove(0)),
+        count => Err(Error::AmbiguousRedriveDestination(count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_aws_and_local_queue_urls() {
+        for url in [
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/orders",
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/orders-dlq.fifo",
+            "http://localhost:4566/000000000000/orders",
+            "http://localhost:9324/queue/orders_dlq",
+        ] {
+            assert_eq!(validate_queue_url(url), Ok(()), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_queue_urls() {
+        for url in [
+            "sqs.eu-west-1.amazonaws.com/123456789012/orders",
+            "https:///123456789012/orders",
+            "https://sqs.eu-west-1.amazonaws.com/",
+            "https://sqs.eu-west-1.amazonaws.com/orders",
+            "https://sqs.eu-west-1.amazonaws.com/12345/orders",
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/orders?",
+        ] {
+            assert!(validate_queue_url(url).is_err(), "{}", url);
+        }
+
+        let fifo_url = |len| {
+            format!(
+                "https://sqs.eu-west-1.amazonaws.com/123456789012/{}.fifo",
+                "q".repeat(len)
+            )
+        };
+        assert_eq!(validate_queue_url(&fifo_url(75)), Ok(()));
+        assert!(validate_queue_url(&fifo_url(76)).is_err());
+    }
+}
@@ -0,0 +1,4096 @@
+use crate::checkpoint::Checkpoint;
+use crate::error::Error;
+use crate::output::{Event, Output};
+use crate::queues::{check_kms_key, queue_details, QueueDetails, MAX_RETENTION_SECONDS};
+use crate::report::{Report, ReportRow};
+use crate::retry::RetryPolicy;
+use crate::shutdown::Shutdown;
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use rusoto_sqs::{
+    DeleteMessageBatchRequest, DeleteMessageBatchRequestEntry, Message, MessageAttributeValue,
+    MessageSystemAttributeValue, ReceiveMessageRequest, SendMessageBatchRequest,
+    SendMessageBatchRequestEntry, Sqs,
+};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::future::{poll_fn, Future};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// SQS won't return more than 10 messages from a single receive
+pub const MAX_BATCH_SIZE: i64 = 10;
+
+/// The longest SQS can delay delivery of a message by, in seconds
+pub const MAX_DELAY_SECONDS: i64 = 900;
+
+/// The longest SQS will wait for messages to arrive before returning an empty receive
+pub const MAX_WAIT_TIME_SECONDS: i64 = 20;
+
+/// The longest SQS will hide a received message for, in seconds
+pub const MAX_VISIBILITY_TIMEOUT: i64 = 43_200;
+
+/// The largest message SQS accepts, in bytes, counting the body and message attributes
+pub const MAX_MESSAGE_SIZE: usize = 262_144;
+
+// Sent in place of empty bodies, which SQS rejects
+const EMPTY_BODY_PLACEHOLDER: &str = "<empty>";
+
+// The only system attribute SQS accepts on send. The others, like the sent timestamp and receive
+// count, are set by SQS itself.
+const TRACE_HEADER_ATTRIBUTE: &str = "AWSTraceHeader";
+
+// The system attribute with when a message was first sent to its queue, in milliseconds since the
+// epoch
+const SENT_TIMESTAMP_ATTRIBUTE: &str = "SentTimestamp";
+
+/// The message attribute a message's original sent time is kept in when preserving timestamps.
+pub const ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE: &str = "OriginalSentTimestamp";
+
+// The most message attributes SQS allows on a message
+const MAX_MESSAGE_ATTRIBUTES: usize = 10;
+
+// The system attribute holding a message's group ID when it's received from a FIFO queue
+const MESSAGE_GROUP_ID_ATTRIBUTE: &str = "MessageGroupId";
+
+// The delete error for a receipt handle that's no longer valid, usually because the message's
+// visibility timeout expired and it was received again
+const RECEIPT_HANDLE_INVALID: &str = "ReceiptHandleIsInvalid";
+
+// The error for a message sent to a queue with too many messages in flight, which for a FIFO
+// queue is 20,000 in total or in one message group
+const OVER_LIMIT_CODE: &str = "OverLimit";
+
+// How long a FIFO queue drops messages sent with a deduplication ID it's already seen
+const DEDUP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+// Rough time taken by a single SQS request that succeeds first time
+const REQUEST_TIME: Duration = Duration::from_secs(1);
+
+/// Number of consecutive empty receives before a queue is considered drained, unless a replay
+/// asks for more
+pub const DEFAULT_EMPTY_RECEIVES: u32 = 2;
+
+/// What to replay and how.
+pub struct ReplayOptions {
+    /// Queues to receive from in turn. The replay carries on until every one of them is drained.
+    pub source_urls: Vec<String>,
+    /// Every message is sent to all of these, and only deleted from the source queue once it's
+    /// been sent to each of them
+    pub dest_urls: Vec<String>,
+    /// Required when a destination is a FIFO queue, unless every message has the
+    /// `group_id_attribute`, and ignored otherwise
+    pub message_group_id: Option<String>,
+    /// Message attribute to take each message's group ID from, falling back to
+    /// `message_group_id` when a message doesn't have it
+    pub group_id_attribute: Option<String>,
+    /// Message attribute to take each message's deduplication ID from when sending to a FIFO
+    /// queue. Messages without it get an ID from `dedup_strategy`.
+    pub dedup_id_attribute: Option<String>,
+    pub dedup_strategy: DedupStrategy,
+    /// Stop after replaying this many messages
+    pub max_messages: Option<usize>,
+    /// Stop receiving once the replay has been running this long. Batches already received are
+    /// still sent and deleted.
+    pub max_duration: Option<Duration>,
+    /// Number of messages to request in each receive, between 1 and `MAX_BATCH_SIZE`
+    pub batch_size: i64,
+    /// Seconds each receive waits for messages to arrive, up to `MAX_WAIT_TIME_SECONDS`. 0 uses
+    /// short polling.
+    pub wait_time_seconds: i64,
+    /// Consecutive empty receives before a source queue is considered drained. SQS is
+    /// distributed, so a single empty receive doesn't mean there's nothing left.
+    pub max_empty_receives: u32,
+    /// Keep receiving until interrupted rather than stopping once the sources are drained,
+    /// forwarding new messages as they arrive. Short polling is switched to the longest wait SQS
+    /// allows so an empty queue isn't polled in a tight loop.
+    pub follow: bool,
+    /// Keep long polling until the first message arrives before counting empty receives, so a
+    /// replay started ahead of the messages it's for doesn't find the sources empty and stop
+    pub wait_for_messages: bool,
+    /// Give up waiting for the first message after this long, going on to stop once the sources
+    /// are drained as usual
+    pub startup_timeout: Option<Duration>,
+    /// Hold received messages back for up to this long so they're sent in full batches of
+    /// `MAX_BATCH_SIZE` when the source only has a few at a time. Fewer requests are made at the
+    /// cost of each message arriving later, and the window has to be well within
+    /// `visibility_timeout` so buffered messages aren't received again.
+    pub batch_window: Option<Duration>,
+    /// Report the replay as moving messages out of a dead-letter queue
+    pub redrive: bool,
+    /// Fail before receiving anything unless every destination is a FIFO queue, with `Some(true)`,
+    /// or a standard queue, with `Some(false)`. Catches a replay into the wrong kind of queue,
+    /// which would otherwise fail on the first send or lose the message ordering.
+    pub require_fifo: Option<bool>,
+    /// Allow the source and destination to be the same queue, which moves every message to the
+    /// back of the queue
+    pub allow_same_queue: bool,
+    /// Delete messages from the sources even without a destination to send them to, which is
+    /// otherwise refused as it would lose them
+    pub force: bool,
+    /// Seconds that received messages stay hidden from other consumers while they're replayed,
+    /// up to `MAX_VISIBILITY_TIMEOUT`. Dry runs always use 0.
+    pub visibility_timeout: i64,
+    /// Seconds to delay delivery of each replayed message by, up to `MAX_DELAY_SECONDS`. Each
+    /// message gets a random delay from the range, so a wide range spreads out when a backlog
+    /// becomes visible. FIFO queues only support delays set on the queue itself.
+    pub delay_seconds: Option<RangeInclusive<i64>>,
+    /// Only replay messages with a body matching this. Other messages are left in the source
+    /// queue and become visible again once their visibility timeout expires.
+    pub filter: Option<Regex>,
+    /// Only replay messages with all of these string attribute names and values. Like `filter`,
+    /// other messages are left in the source queue.
+    pub attribute_filters: Vec<(String, String)>,
+    /// The message attributes to receive and so replay, or `All`. Any a filter or the FIFO
+    /// options read are received as well, but no others are replayed if it's empty.
+    pub message_attribute_names: Vec<String>,
+    /// The system attributes to receive, e.g. `SentTimestamp` or `AWSTraceHeader`, or `All`.
+    /// Any the other options read are received as well. The trace header is only forwarded if
+    /// it's received.
+    pub system_attribute_names: Vec<String>,
+    /// Only replay messages first sent to the source queue at or after this time. Like `filter`,
+    /// other messages are left in the source queue, as are any without a sent timestamp.
+    pub sent_since: Option<DateTime<Utc>>,
+    /// Only replay messages first sent to the source queue before this time
+    pub sent_until: Option<DateTime<Utc>>,
+    /// Fraction of messages to replay, between 0 and 1, chosen at random. Like `filter`, the rest
+    /// are left in the source queue.
+    pub sample_rate: Option<f64>,
+    /// Seed for choosing which messages are sampled, so a run over the same messages in the same
+    /// order samples them the same way
+    pub sample_seed: Option<u64>,
+    /// What to do with messages that have an empty body. The command line defaults to skipping
+    /// them.
+    pub on_empty: EmptyBodies,
+    /// Literal find and replace pairs applied to each body in order before it's sent. The
+    /// message in the source queue keeps its original body, so one that fails to send is left
+    /// there unchanged.
+    pub replacements: Vec<(String, String)>,
+    /// Add the time each message was originally sent to the source queue as an
+    /// `ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE` message attribute, in milliseconds since the epoch. A
+    /// message that already has one, from being replayed before, keeps it.
+    pub preserve_timestamp: bool,
+    /// Leave messages in the source queue after sending them
+    pub copy: bool,
+    /// What to do about messages sent to a FIFO destination with a deduplication ID that was
+    /// already sent there within the last five minutes
+    pub fifo_retry_strategy: FifoRetryStrategy,
+    /// What to delete from the source queue when only some of a batch was sent
+    pub on_partial_batch: PartialBatch,
+    /// Keep going after messages fail to send, leaving them in the source queue. Otherwise no
+    /// more batches are received after the first failure, and a batch that can't be sent at all
+    /// stops the replay with an error.
+    pub continue_on_error: bool,
+    /// Only report the messages that would be replayed
+    pub dry_run: bool,
+    /// Fail with `Error::NothingToReplay` before receiving anything if SQS reports no
+    /// messages available in any of the sources, so it can be told apart from a replay that
+    /// did something
+    pub fail_if_empty: bool,
+    /// Maximum number of batches being sent and deleted at once. FIFO destinations are limited to
+    /// one so that messages keep their order within the message group. Nothing more is received
+    /// while this many batches, plus `prefetch`, are in flight, so received messages can't pile up in memory when
+    /// the destination is slower than the source.
+    pub concurrency: usize,
+    /// Let FIFO destinations have up to `concurrency` batches in flight too, as long as no two of
+    /// them have messages from the same message group. Messages are still sent in order within
+    /// each group, but groups no longer wait for each other.
+    pub concurrency_per_group: bool,
+    /// Batches to receive ahead of the ones being sent, queued until there's room for them to be
+    /// sent. Receives already overlap with sends, so this smooths out bursts where a source's
+    /// receives are slow, at the cost of queued messages using up more of their visibility
+    /// timeout before they're sent.
+    pub prefetch: usize,
+    /// Receive and send one message at a time so the destination gets them in exactly the order
+    /// they were received, taking each message's group ID from the source queue when it has one.
+    /// This overrides `batch_size` and `concurrency`.
+    pub preserve_order: bool,
+    /// Maximum number of messages to send per second, across all batches in flight
+    pub rate_limit: Option<f64>,
+    pub retry_policy: RetryPolicy,
+    /// File recording the IDs of messages that have been replayed, so a later run with the same
+    /// file skips them. Mostly useful with `copy`, where replayed messages stay in the source.
+    pub checkpoint_file: Option<PathBuf>,
+    /// CSV file to append a row to for each message at each destination, saying whether it was
+    /// sent and deleted from the source
+    pub report_file: Option<PathBuf>,
+    /// Asked before each batch is sent. Batches that aren't approved are left in the source queue,
+    /// and once one is approved with `Approval::All` no more are asked about.
+    pub approve: Option<ApproveBatch>,
+    /// Stops receiving new batches once requested. Batches already received are still sent and
+    /// deleted, and the summary covers whatever was replayed up to that point.
+    pub shutdown: Shutdown,
+}
+
+// Checks the replay won't delete messages it shouldn't, before anything is received:
+//
+// - No destination can be a source, unless `allow_same_queue` says that's intended. Each message
+//   would be received, sent straight back and received again until the run is stopped or hits
+//   its limit, even when copying.
+// - Messages that are deleted need a destination, with a URL, to be sent to first. A batch with
+//   nowhere to go counts as sent to everywhere, so `force` is needed to delete it anyway.
+//
+// Dry runs and copies never delete anything, so only the first applies to them.
+fn check_safe_to_delete(options: &ReplayOptions) -> Result<(), Error> {
+    let same_queue = options.source_urls.iter().any(|source_url| {
+        options
+            .dest_urls
+            .iter()
+            .any(|dest_url| is_same_queue(source_url, dest_url))
+    });
+    if same_queue && !options.allow_same_queue {
+        return Err(Error::SameSourceAndDestination);
+    }
+
+    if options.dry_run || options.copy || options.force {
+        return Ok(());
+    }
+    if options.dest_urls.is_empty() {
+        return Err(Error::UnsafeDelete("there are no destination queues"));
+    }
+    if options
+        .dest_urls
+        .iter()
+        .any(|dest_url| dest_url.trim().is_empty())
+    {
+        return Err(Error::UnsafeDelete("a destination queue URL is empty"));
+    }
+    Ok(())
+}
+
+// Whether two URLs are for the same queue, ignoring the scheme, a trailing slash, the case of the
+// host and whether it's the legacy `<region>.queue.amazonaws.com` form of the SQS endpoint
+fn is_same_queue(a: &str, b: &str) -> bool {
+    let normalise = |url: &str| {
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let (host, path) = url.split_once('/').unwrap_or((url, ""));
+        let host = host.to_ascii_lowercase();
+        let host = match host.split_once(".queue.amazonaws.com") {
+            Some((region, rest)) => format!("sqs.{}.amazonaws.com{}", region, rest),
+            None => host,
+        };
+        format!("{}/{}", host, path.trim_end_matches('/'))
+    };
+    normalise(a) == normalise(b)
+}
+
+/// Where deduplication IDs come from for messages sent to a FIFO queue without a deduplication ID
+/// attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupStrategy {
+    /// A new ID every time, so replaying a message twice sends it twice. Content-based
+    /// deduplication is left to SQS.
+    Random,
+    /// The source message ID, so a message replayed again within SQS's five minute
+    /// deduplication window is dropped
+    MessageId,
+    /// A SHA-256 hash of the body, so messages with the same body are only delivered once within
+    /// the deduplication window
+    BodyHash,
+}
+
+/// What to do with messages that have an empty body, which SQS won't send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmptyBodies {
+    /// Leave them in the source queue
+    Skip,
+    /// Send them with a body of `<empty>` instead
+    Placeholder,
+    /// Count them as failed, which stops the replay unless it continues on errors
+    Fail,
+}
+
+/// What to do when a message is about to be sent to a FIFO queue with a deduplication ID that
+/// this replay already sent there within SQS's five minute deduplication window, as happens when
+/// a message comes back around, e.g. after failing again in the queue it was redriven to. SQS
+/// would accept it without delivering it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FifoRetryStrategy {
+    /// Send it anyway, counting it in the summary as possibly deduplicated
+    Send,
+    /// Wait for the window to pass before sending its batch, which needs a visibility timeout long
+    /// enough to cover the wait
+    Wait,
+    /// Send it with a new random deduplication ID so it's delivered again
+    NewId,
+}
+
+/// What to do when some of the messages in a batch fail to send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartialBatch {
+    /// Delete the messages that were sent, leaving the rest in the source queue
+    DeleteSucceeded,
+    /// Leave the whole batch in the source queue, so the messages that were sent will be
+    /// sent again the next time it's replayed
+    DeleteNone,
+    /// Send the failed messages again, as many times as the retry policy allows, then delete
+    /// whichever were sent. Messages SQS rejected as invalid aren't retried, and retried messages
+    /// can arrive after later ones in the batch.
+    Retry,
+}
+
+/// Whether a batch should be replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Approval {
+    Yes,
+    No,
+    /// This batch and every one after it
+    All,
+}
+
+/// Decides whether each batch received should be replayed.
+pub type ApproveBatch = Box<dyn Fn(&[Message]) -> Approval>;
+
+/// Why a replay stopped receiving messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StopReason {
+    /// The source queue ran out of messages
+    #[default]
+    Drained,
+    /// `max_messages` were replayed
+    MessageLimit,
+    /// The replay ran for `max_duration`
+    TimeLimit,
+    /// A shutdown was requested
+    Interrupted,
+    /// A message failed and the replay wasn't continuing on errors
+    Failed,
+}
+
+/// The outcome of a replay.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplaySummary {
+    /// Messages received from the source queue, including any received more than once
+    pub received: usize,
+    /// Messages sent to the destination queue, or that would have been in a dry run
+    pub replayed: usize,
+    pub deleted: usize,
+    /// Messages left in the source queue because they didn't match the filter
+    pub skipped: usize,
+    /// Messages that couldn't be sent to the destination or deleted from the source
+    pub failed: usize,
+    /// Messages that were sent but couldn't be deleted because their receipt handle had expired.
+    /// They're also counted as failed, and will likely be replayed again.
+    pub expired: usize,
+    /// Messages sent to a FIFO queue with a deduplication ID already sent there less than five
+    /// minutes before, which SQS may have accepted without delivering. They're also counted as
+    /// replayed.
+    pub deduplicated: usize,
+    /// SQS requests made, counting each retry
+    pub receive_requests: usize,
+    pub send_requests: usize,
+    pub delete_requests: usize,
+    pub duration: Duration,
+    pub stop_reason: StopReason,
+}
+
+impl ReplaySummary {
+    fn add(&mut self, batch: ReplaySummary) {
+        self.received += batch.received;
+        self.replayed += batch.replayed;
+        self.deleted += batch.deleted;
+        self.skipped += batch.skipped;
+        self.failed += batch.failed;
+        self.expired += batch.expired;
+        self.deduplicated += batch.deduplicated;
+        self.receive_requests += batch.receive_requests;
+        self.send_requests += batch.send_requests;
+        self.delete_requests += batch.delete_requests;
+    }
+
+    /// The average rate messages were replayed at over the whole run.
+    pub fn messages_per_second(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds > 0.0 {
+            self.replayed as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Receive, send and delete requests made, counting each retry.
+    pub fn requests(&self) -> usize {
+        self.receive_requests + self.send_requests + self.delete_requests
+    }
+}
+
+/// Moves messages from the source queue to the destination queues until the source is drained.
+/// Messages are received from and deleted with `source_client` and sent with `dest_client`, so
+/// the queues can be in different regions or accounts.
+pub async fn replay(
+    source_client: &dyn Sqs,
+    dest_client: &dyn Sqs,
+    options: ReplayOptions,
+    output: &Output,
+) -> Result<ReplaySummary, Error> {
+    let started = Instant::now();
+
+    check_safe_to_delete(&options)?;
+
+    output.emit(Event::ReplayStarted {
+        source_queue_urls: &options.source_urls,
+        destination_queue_urls: &options.dest_urls,
+        dry_run: options.dry_run,
+        copy: options.copy,
+        redrive: options.redrive,
+    });
+
+    // Everything that would stop the replay part way through is checked before any messages are
+    // received
+    let mut available = 0;
+    for source_url in options.source_urls.iter() {
+        available += queue_details(source_client, source_url, &options.retry_policy, output)
+            .await?
+            .visible;
+    }
+    if options.fail_if_empty && available == 0 {
+        return Err(Error::NothingToReplay);
+    }
+    let mut destinations = Vec::new();
+    for dest_url in options.dest_urls.iter() {
+        let details = queue_details(dest_client, dest_url, &options.retry_policy, output).await?;
+        if let Some(fifo) = options.require_fifo {
+            if details.fifo != fifo {
+                return Err(Error::WrongQueueType(dest_url.clone(), fifo));
+            }
+        }
+        check_kms_key(&details, dest_url, output);
+        let fifo_ids = fifo_ids(
+            &details,
+            FifoIds {
+                group_id: options.message_group_id.clone(),
+                group_id_attribute: options.group_id_attribute.clone(),
+                dedup_id_attribute: options.dedup_id_attribute.clone(),
+                dedup_strategy: options.dedup_strategy,
+                content_based_deduplication: details.content_based_deduplication,
+                source_group_id: options.preserve_order,
+                retry_strategy: options.fifo_retry_strategy,
+                recently_sent: RefCell::default(),
+                over_limit: RefCell::default(),
+            },
+            output,
+        )?;
+        if options.delay_seconds.is_some() && fifo_ids.is_some() {
+            return Err(Error::DelayWithFifoDestination);
+        }
+        destinations.push((dest_url.as_str(), fifo_ids));
+    }
+    let any_fifo = destinations.iter().any(|(_, fifo_ids)| fifo_ids.is_some());
+
+    // Messages from the same group in batches sent side by side could be interleaved in the
+    // destination
+    let concurrency = if options.preserve_order {
+        1
+    } else if any_fifo && options.concurrency > 1 && !options.concurrency_per_group {
+        output.emit(Event::Warning {
+            message:
+                "Sending one batch at a time to preserve ordering in the FIFO destination queue",
+        });
+        1
+    } else {
+        options.concurrency.max(1)
+    };
+
+    // A batch that's still being sent or deleted when its messages become visible again will be
+    // received and replayed a second time. Sends to a destination that's over its limit of
+    // messages in flight back off once more on top of the usual retries.
+    let mut batch_time = 2 * (REQUEST_TIME + options.retry_policy.total_delay())
+        + options.retry_policy.total_delay()
+        + options.batch_window.unwrap_or_default();
+    if any_fifo && options.fifo_retry_strategy == FifoRetryStrategy::Wait {
+        batch_time += DEDUP_WINDOW;
+    }
+    // Queued batches also wait for the ones ahead of them
+    batch_time *= 1 + ((options.prefetch + concurrency - 1) / concurrency) as u32;
+    if !options.dry_run && Duration::from_secs(options.visibility_timeout as u64) < batch_time {
+        output.emit(Event::Warning {
+            message: &format!(
+                "Visibility timeout of {}s may expire before a batch is replayed, which can take up to {}s with retries",
+                options.visibility_timeout,
+                batch_time.as_secs_f64().ceil()
+            ),
+        });
+    }
+
+    let mut checkpoint = match &options.checkpoint_file {
+        Some(path) => Some(Checkpoint::open(path)?),
+        None => None,
+    };
+    let mut report = match &options.report_file {
+        Some(path) => Some(Report::open(path)?),
+        None => None,
+    };
+
+    // Nothing's deleted, so every message comes back around once its visibility timeout expires
+    let leaves_every_message = options.dry_run || options.copy;
+    let mut rng = match options.sample_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // Batches of one mean a failure can't leave later messages in the batch to be sent first
+    let batch_size = if options.preserve_order {
+        1
+    } else {
+        options.batch_size
+    };
+    let mut more_messages = true;
+    // Only used when the queue stops giving out messages, rather than the loop's other conditions
+    let mut stop_reason = StopReason::Drained;
+    let mut batch_no = 1;
+    let mut summary = ReplaySummary::default();
+    // The sources that haven't been drained yet, with how many empty receives each has had in a
+    // row, and the one to receive from next
+    let mut sources: Vec<(&str, u32)> = options
+        .source_urls
+        .iter()
+        .map(|source_url| (source_url.as_str(), 0))
+        .collect();
+    let mut turn = 0;
+    // The messages deliberately left in the source queue, and when each was last received, so
+    // ones that come back around aren't handled twice. Messages that failed aren't included, so
+    // they're tried again when they come back.
+    let mut left_in_source: HashMap<String, Instant> = HashMap::new();
+    // Batches that have been received but not yet sent and deleted, in the order they were
+    // received. Only the first `concurrency` are sent, and the rest are queued behind them.
+    let mut in_flight: Vec<InFlight<'_>> = Vec::new();
+    let mut pending = 0;
+    let mut rate_limiter = options.rate_limit.map(RateLimiter::new);
+    let mut approved_all = false;
+    let mut batch_window = options
+        .batch_window
+        .filter(|_| !options.dry_run)
+        .map(BatchWindow::new);
+    let start_batch = |source_url, messages| -> BatchFuture<'_> {
+        Box::pin(replay_batch(
+            source_client,
+            dest_client,
+            &options,
+            source_url,
+            &destinations,
+            messages,
+            output,
+        ))
+    };
+
+    let wait_time_seconds = if options.follow && options.wait_time_seconds == 0 {
+        MAX_WAIT_TIME_SECONDS
+    } else {
+        options.wait_time_seconds
+    };
+    let (message_attribute_names, system_attribute_names) = attribute_names(&options);
+    let out_of_time = || {
+        options
+            .max_duration
+            .is_some_and(|max_duration| started.elapsed() >= max_duration)
+    };
+    while more_messages
+        && !options.shutdown.is_requested()
+        && !out_of_time()
+        && (options.continue_on_error || summary.failed == 0)
+    {
+        let waiting = options.wait_for_messages
+            && summary.received == 0
+            && options
+                .startup_timeout
+                .map_or(true, |timeout| started.elapsed() < timeout);
+        // Waiting polls for as long as SQS allows, but no longer than is left of the timeout
+        let wait_time_seconds = match options.startup_timeout {
+            Some(timeout) if waiting => MAX_WAIT_TIME_SECONDS.min(
+                timeout
+                    .saturating_sub(started.elapsed())
+                    .as_secs_f64()
+                    .ceil() as i64,
+            ),
+            None if waiting => MAX_WAIT_TIME_SECONDS,
+            _ => wait_time_seconds,
+        };
+        let source = turn % sources.len();
+        let (source_url, empty_receives) = &mut sources[source];
+        let source_url = *source_url;
+        let mut drained = false;
+        let mut ready = Vec::new();
+        output.emit(Event::BatchRequested {
+            batch: batch_no,
+            count: batch_size,
+        });
+        let receive_message_input = ReceiveMessageRequest {
+            queue_url: String::from(source_url),
+            max_number_of_messages: Some(batch_size),
+            wait_time_seconds: Some(wait_time_seconds),
+            // Messages should become visible again straight away when they're only being inspected
+            visibility_timeout: Some(if options.dry_run {
+                0
+            } else {
+                options.visibility_timeout
+            }),
+            attribute_names: Some(system_attribute_names.clone()),
+            message_attribute_names: Some(message_attribute_names.clone()),
+            // Retries reuse the same attempt ID so a FIFO source returns the batch it already
+            // handed out rather than hiding those messages until their visibility timeout expires
+            receive_request_attempt_id: if is_fifo_queue(source_url) {
+                Some(Uuid::new_v4().to_string())
+            } else {
+                None
+            },
+        };
+
+        // Long polling receives are expected to take up to the wait time on top of the request.
+        // Batches in flight keep being sent in the meantime.
+        let mut finished = Vec::new();
+        let mut receive_requests = 0;
+        let result = while_sending(
+            options
+                .retry_policy
+                .allowing(Duration::from_secs(wait_time_seconds as u64))
+                .run(output, || {
+                    receive_requests += 1;
+                    source_client.receive_message(receive_message_input.clone())
+                }),
+            &mut in_flight,
+            concurrency,
+            &mut finished,
+        )
+        .await;
+        summary.receive_requests += receive_requests;
+        for batch in finished {
+            pending -= batch.size;
+            record_batch(&mut summary, &mut checkpoint, &mut report, batch, output)?;
+        }
+        // Whatever was received is left for its visibility timeout to expire, as it would have
+        // been if the failure was noticed before receiving it
+        if !options.continue_on_error && summary.failed > 0 {
+            break;
+        }
+        let result = result
+            .map_err(|error| Error::ReceiveMessage(String::from(source_url), Box::new(error)))?;
+        let messages = result.messages.unwrap_or_default();
+        if messages.is_empty() {
+            // SQS is distributed so a single empty receive doesn't mean the queue is drained
+            if !waiting {
+                *empty_receives += 1;
+            }
+            output.emit(Event::BatchEmpty { batch: batch_no });
+            if *empty_receives >= options.max_empty_receives.max(1) {
+                drained = true;
+            }
+        } else {
+            *empty_receives = 0;
+            summary.received += messages.len();
+            output.emit(Event::BatchReceived {
+                batch: batch_no,
+                count: messages.len(),
+            });
+
+            // A followed queue keeps being received from, so forget messages that have been gone
+            // for longer than SQS could have kept them
+            if options.follow {
+                left_in_source
+                    .retain(|_, received| received.elapsed().as_secs() < MAX_RETENTION_SECONDS);
+            }
+            if messages.iter().all(|m| {
+                m.message_id
+                    .as_ref()
+                    .is_some_and(|message_id| left_in_source.contains_key(message_id))
+            }) {
+                output.emit(Event::BatchAlreadySeen { batch: batch_no });
+                drained = true;
+            }
+
+            let mut to_send: Vec<Message> = Vec::new();
+            for m in messages.iter() {
+                if let Some(message_id) = &m.message_id {
+                    if let Some(received) = left_in_source.get_mut(message_id) {
+                        *received = Instant::now();
+                        output.emit(Event::MessageSkipped {
+                            message_id,
+                            reason: "was already received and left in the source queue",
+                        });
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    if leaves_every_message {
+                        left_in_source.insert(message_id.clone(), Instant::now());
+                    }
+
+                    if m.receipt_handle.is_none() {
+                        output.emit(Event::MissingReceiptHandle { message_id });
+                        left_in_source.insert(message_id.clone(), Instant::now());
+                        continue;
+                    }
+
+                    if checkpoint.as_ref().is_some_and(|c| c.contains(message_id)) {
+                        left_in_source.insert(message_id.clone(), Instant::now());
+                        output.emit(Event::MessageSkipped {
+                            message_id,
+                            reason: "was replayed by an earlier run",
+                        });
+                        summary.skipped += 1;
+                        continue;
+                    }
+
+                    if m.body.as_deref().unwrap_or_default().is_empty() {
+                        match options.on_empty {
+                            EmptyBodies::Skip => {
+                                left_in_source.insert(message_id.clone(), Instant::now());
+                                output.emit(Event::MessageSkipped {
+                                    message_id,
+                                    reason: "has an empty body",
+                                });
+                                summary.skipped += 1;
+                                continue;
+                            }
+                            EmptyBodies::Fail => {
+                                output.emit(Event::Error {
+                                    message: &format!(
+                                        "Message ID {} has an empty body, which SQS won't send",
+                                        message_id
+                                    ),
+                                });
+                                summary.failed += 1;
+                                if !options.continue_on_error {
+                                    more_messages = false;
+                                    break;
+                                }
+                                continue;
+                            }
+                            EmptyBodies::Placeholder => {}
+                        }
+                    }
+
+                    if !matches_filters(m, &options) {
+                        left_in_source.insert(message_id.clone(), Instant::now());
+                        output.emit(Event::MessageSkipped {
+                            message_id,
+                            reason: "doesn't match the filter",
+                        });
+                        summary.skipped += 1;
+                        continue;
+                    }
+
+                    if !sent_in_window(m, &options) {
+                        left_in_source.insert(message_id.clone(), Instant::now());
+                        output.emit(Event::MessageSkipped {
+                            message_id,
+                            reason: "wasn't sent in the time window",
+                        });
+                        summary.skipped += 1;
+                        continue;
+                    }
+
+                    if let Some(sample_rate) = options.sample_rate {
+                        if !rng.gen_bool(sample_rate) {
+                            left_in_source.insert(message_id.clone(), Instant::now());
+                            output.emit(Event::MessageSkipped {
+                                message_id,
+                                reason: "wasn't sampled",
+                            });
+                            summary.skipped += 1;
+                            continue;
+                        }
+                    }
+
+                    output.emit(Event::MessageReceived {
+                        message_id,
+                        body: m.body.as_deref().unwrap_or(EMPTY_BODY_PLACEHOLDER),
+                        receipt_handle: m.receipt_handle.as_deref(),
+                    });
+                    to_send.push(transform(m, &options, output));
+
+                    // Anything left in this batch becomes visible again once its visibility timeout expires
+                    let buffered = batch_window.as_ref().map_or(0, BatchWindow::len);
+                    if options.max_messages
+                        == Some(summary.replayed + pending + buffered + to_send.len())
+                    {
+                        more_messages = false;
+                        stop_reason = StopReason::MessageLimit;
+                        break;
+                    }
+                }
+            }
+
+            if let Some(approve) = options.approve.as_ref().filter(|_| !approved_all) {
+                if !to_send.is_empty() {
+                    match approve(&to_send) {
+                        Approval::Yes => {}
+                        Approval::All => approved_all = true,
+                        Approval::No => {
+                            for m in to_send.drain(..) {
+                                if let Some(message_id) = &m.message_id {
+                                    left_in_source.insert(message_id.clone(), Instant::now());
+                                }
+                                output.emit(Event::MessageSkipped {
+                                    message_id: m.message_id.as_deref().unwrap_or("<unknown>"),
+                                    reason: "wasn't approved",
+                                });
+                                summary.skipped += 1;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if options.dry_run {
+                output.emit(Event::WouldReplay {
+                    count: to_send.len(),
+                });
+                summary.replayed += to_send.len();
+            } else if let Some(batch_window) = &mut batch_window {
+                batch_window.add(source_url, to_send);
+            } else if !to_send.is_empty() {
+                ready.push((source_url, to_send));
+            }
+        }
+
+        // A followed queue is never finished with, however long it's been empty
+        drained &= !options.follow;
+        if let Some(batch_window) = &mut batch_window {
+            ready.extend(batch_window.ready(source_url, drained));
+        }
+        for (source_url, to_send) in ready {
+            if let Some(rate_limiter) = &mut rate_limiter {
+                rate_limiter.wait(to_send.len()).await;
+            }
+            pending += to_send.len();
+            // A batch waits for any others with messages from the same groups, so each group's
+            // messages arrive in the order they were received
+            let groups = message_groups(&destinations, &to_send);
+            while in_flight
+                .iter()
+                .any(|batch| !batch.groups.is_disjoint(&groups))
+            {
+                let batch = next_finished(&mut in_flight, concurrency).await;
+                pending -= batch.size;
+                record_batch(&mut summary, &mut checkpoint, &mut report, batch, output)?;
+            }
+            in_flight.push(InFlight {
+                size: to_send.len(),
+                batch_no,
+                groups,
+                batch: start_batch(source_url, to_send),
+            });
+            if in_flight.len() >= concurrency + options.prefetch {
+                let batch = next_finished(&mut in_flight, concurrency).await;
+                pending -= batch.size;
+                record_batch(&mut summary, &mut checkpoint, &mut report, batch, output)?;
+            }
+        }
+
+        // The next source takes the drained one's place in the rotation
+        if drained {
+            sources.remove(source);
+            turn = source;
+            if sources.is_empty() {
+                more_messages = false;
+            }
+        } else {
+            turn = source + 1;
+        }
+        batch_no += 1;
+    }
+
+    summary.stop_reason = if !options.continue_on_error && summary.failed > 0 {
+        StopReason::Failed
+    } else if options.shutdown.is_requested() {
+        StopReason::Interrupted
+    } else if more_messages {
+        StopReason::TimeLimit
+    } else {
+        stop_reason
+    };
+
+    // Like buffered messages, queued batches that haven't started are left in the source queue if
+    // the replay stopped because something failed
+    if !options.continue_on_error && summary.failed > 0 {
+        in_flight.truncate(concurrency);
+    }
+
+    // Buffered messages were received before the replay stopped, so they're still sent unless it
+    // stopped because something failed
+    let leftovers = match &mut batch_window {
+        Some(batch_window) if options.continue_on_error || summary.failed == 0 => {
+            batch_window.flush()
+        }
+        _ => Vec::new(),
+    };
+    for (source_url, to_send) in leftovers {
+        if let Some(rate_limiter) = &mut rate_limiter {
+            rate_limiter.wait(to_send.len()).await;
+        }
+        let groups = message_groups(&destinations, &to_send);
+        while in_flight.len() >= concurrency + options.prefetch
+            || in_flight
+                .iter()
+                .any(|batch| !batch.groups.is_disjoint(&groups))
+        {
+            let batch = next_finished(&mut in_flight, concurrency).await;
+            record_batch(&mut summary, &mut checkpoint, &mut report, batch, output)?;
+        }
+        in_flight.push(InFlight {
+            size: to_send.len(),
+            // The buffered messages were all received by the last batch
+            batch_no: batch_no - 1,
+            groups,
+            batch: start_batch(source_url, to_send),
+        });
+    }
+
+    while !in_flight.is_empty() {
+        let batch = next_finished(&mut in_flight, concurrency).await;
+        record_batch(&mut summary, &mut checkpoint, &mut report, batch, output)?;
+    }
+
+    summary.duration = started.elapsed();
+    output.emit(Event::ReplayFinished {
+        summary: &summary,
+        requested: options.max_messages,
+        redrive: options.redrive,
+    });
+    Ok(summary)
+}
+
+// Messages held back until there are enough from one source to fill a batch or the window since
+// the first of them was received has passed. Each source has its own buffer since a batch is
+// deleted from the queue it was received from.
+struct BatchWindow<'a> {
+    window: Duration,
+    buffers: Vec<(&'a str, Instant, Vec<Message>)>,
+}
+
+impl<'a> BatchWindow<'a> {
+    fn new(window: Duration) -> BatchWindow<'a> {
+        BatchWindow {
+            window,
+            buffers: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.buffers
+            .iter()
+            .map(|(_, _, messages)| messages.len())
+            .sum()
+    }
+
+    fn add(&mut self, source_url: &'a str, messages: Vec<Message>) {
+        if messages.is_empty() {
+            return;
+        }
+        match self
+            .buffers
+            .iter_mut()
+            .find(|(url, _, _)| *url == source_url)
+        {
+            Some((_, _, buffered)) => buffered.extend(messages),
+            None => self.buffers.push((source_url, Instant::now(), messages)),
+        }
+    }
+
+    // Takes every full batch, and everything else buffered from sources whose window has passed.
+    // A drained source won't get any more messages, so its buffer is emptied straight away.
+    fn ready(&mut self, source_url: &str, drained: bool) -> Vec<(&'a str, Vec<Message>)> {
+        let mut ready = Vec::new();
+        let window = self.window;
+        self.buffers.retain_mut(|(url, first_received, messages)| {
+            while messages.len() >= MAX_BATCH_SIZE as usize {
+                ready.push((*url, messages.drain(..MAX_BATCH_SIZE as usize).collect()));
+            }
+            let expired = first_received.elapsed() >= window || (drained && *url == source_url);
+            if expired && !messages.is_empty() {
+                ready.push((*url, std::mem::take(messages)));
+            }
+            if messages.is_empty() {
+                return false;
+            }
+            // The window starts again for whatever's left over from a full batch
+            if ready.iter().any(|(ready_url, _)| ready_url == url) {
+                *first_received = Instant::now();
+            }
+            true
+        });
+        ready
+    }
+
+    // Takes everything that's buffered, in batches no bigger than SQS allows
+    fn flush(&mut self) -> Vec<(&'a str, Vec<Message>)> {
+        let mut ready = Vec::new();
+        for (url, _, mut messages) in self.buffers.drain(..) {
+            while !messages.is_empty() {
+                let size = messages.len().min(MAX_BATCH_SIZE as usize);
+                ready.push((url, messages.drain(..size).collect()));
+            }
+        }
+        ready
+    }
+}
+
+// Spaces batches out so that, on average, no more than `rate` messages are sent each second
+struct RateLimiter {
+    started: Instant,
+    rate: f64,
+    sent: usize,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> RateLimiter {
+        RateLimiter {
+            started: Instant::now(),
+            rate,
+            sent: 0,
+        }
+    }
+
+    async fn wait(&mut self, count: usize) {
+        let delay = self.delay(Instant::now());
+        if delay > Duration::from_secs(0) {
+            tokio::time::delay_for(delay).await;
+        }
+        self.sent += count;
+    }
+
+    // How long until everything sent so far is within the rate
+    fn delay(&self, now: Instant) -> Duration {
+        let due = self.started + Duration::from_secs_f64(self.sent as f64 / self.rate);
+        due.saturating_duration_since(now)
+    }
+}
+
+fn matches_filters(message: &Message, options: &ReplayOptions) -> bool {
+    let body_matches = options.filter.as_ref().map_or(true, |filter| {
+        filter.is_match(message.body.as_deref().unwrap_or_default())
+    });
+    body_matches
+        && options.attribute_filters.iter().all(|(name, value)| {
+            string_attribute(message, &Some(name.clone())).as_ref() == Some(value)
+        })
+}
+
+// Whether the message was sent between `sent_since` and `sent_until`, going by its sent timestamp
+fn sent_in_window(message: &Message, options: &ReplayOptions) -> bool {
+    if options.sent_since.is_none() && options.sent_until.is_none() {
+        return true;
+    }
+    let sent_timestamp = match message
+        .attributes
+        .as_ref()
+        .and_then(|attributes| attributes.get(SENT_TIMESTAMP_ATTRIBUTE))
+        .and_then(|sent_timestamp| sent_timestamp.parse::<i64>().ok())
+    {
+        Some(sent_timestamp) => sent_timestamp,
+        None => return false,
+    };
+    options
+        .sent_since
+        .map_or(true, |since| sent_timestamp >= since.timestamp_millis())
+        && options
+            .sent_until
+            .map_or(true, |until| sent_timestamp < until.timestamp_millis())
+}
+
+// A copy of the message with the replacements applied to its body, reporting both bodies if
+// anything changed
+fn transform(message: &Message, options: &ReplayOptions, output: &Output) -> Message {
+    let mut message = message.clone();
+    if options.preserve_timestamp {
+        preserve_timestamp(&mut message, output);
+    }
+    if let Some(body) = &message.body {
+        let transformed = options
+            .replacements
+            .iter()
+            .fold(body.clone(), |body, (from, to)| body.replace(from, to));
+        if transformed != *body {
+            output.emit(Event::MessageTransformed {
+                message_id: message.message_id.as_deref().unwrap_or("<unknown>"),
+                original: body,
+                transformed: &transformed,
+            });
+            message.body = Some(transformed);
+        }
+    }
+    message
+}
+
+// Copies the sent timestamp into a message attribute, unless it has one from an earlier replay
+fn preserve_timestamp(message: &mut Message, output: &Output) {
+    let sent_timestamp = match message
+        .attributes
+        .as_ref()
+        .and_then(|attributes| attributes.get(SENT_TIMESTAMP_ATTRIBUTE))
+    {
+        Some(sent_timestamp) => sent_timestamp.clone(),
+        None => return,
+    };
+    let message_id = message.message_id.as_deref().unwrap_or("<unknown>");
+    let attributes = message.message_attributes.get_or_insert_with(HashMap::new);
+    if attributes.contains_key(ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE) {
+        return;
+    }
+    if attributes.len() >= MAX_MESSAGE_ATTRIBUTES {
+        output.emit(Event::Warning {
+            message: &format!(
+                "Message ID {} already has {} message attributes, the most SQS allows, so its sent timestamp can't be kept",
+                message_id, MAX_MESSAGE_ATTRIBUTES
+            ),
+        });
+        return;
+    }
+    attributes.insert(
+        String::from(ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE),
+        MessageAttributeValue {
+            data_type: String::from("Number"),
+            string_value: Some(sent_timestamp),
+            ..Default::default()
+        },
+    );
+}
+
+fn is_fifo_queue(queue_url: &str) -> bool {
+    queue_url.ends_with(".fifo")
+}
+
+/// Where the group and deduplication IDs for each message sent to a FIFO queue come from.
+pub(crate) struct FifoIds {
+    pub(crate) group_id: Option<String>,
+    pub(crate) group_id_attribute: Option<String>,
+    pub(crate) dedup_id_attribute: Option<String>,
+    pub(crate) dedup_strategy: DedupStrategy,
+    // SQS works out the deduplication ID from the body itself when this is set on the queue
+    pub(crate) content_based_deduplication: bool,
+    // Keep the group ID a message had in a FIFO source queue
+    pub(crate) source_group_id: bool,
+    pub(crate) retry_strategy: FifoRetryStrategy,
+    // When each deduplication ID was last sent, for spotting ones SQS would drop
+    pub(crate) recently_sent: RefCell<HashMap<String, Instant>>,
+    // Groups SQS said had too many messages in flight, and when to carry on sending to them
+    pub(crate) over_limit: RefCell<HashMap<String, Instant>>,
+}
+
+impl FifoIds {
+    fn group_id(&self, message: &Message) -> Option<String> {
+        let source_group_id = match &message.attributes {
+            Some(attributes) if self.source_group_id => {
+                attributes.get(MESSAGE_GROUP_ID_ATTRIBUTE).cloned()
+            }
+            _ => None,
+        };
+        source_group_id
+            .or_else(|| string_attribute(message, &self.group_id_attribute))
+            .or_else(|| self.group_id.clone())
+    }
+
+    fn dedup_id(&self, message: &Message) -> Option<String> {
+        string_attribute(message, &self.dedup_id_attribute).or_else(|| match self.dedup_strategy {
+            DedupStrategy::Random if self.content_based_deduplication => None,
+            DedupStrategy::Random => Some(Uuid::new_v4().to_string()),
+            DedupStrategy::MessageId => message.message_id.clone(),
+            DedupStrategy::BodyHash => Some(hex::encode(Sha256::digest(
+                message.body.as_deref().unwrap_or_default().as_bytes(),
+            ))),
+        })
+    }
+
+    // Works through the entries whose deduplication IDs were sent less than five minutes ago,
+    // noting which ones are repeated or giving them new IDs, and returns how long to wait before
+    // sending them
+    fn check_recently_sent(
+        &self,
+        entries: &mut [SendMessageBatchRequestEntry],
+        repeated: &mut HashSet<String>,
+    ) -> Duration {
+        let mut recently_sent = self.recently_sent.borrow_mut();
+        recently_sent.retain(|_, sent| sent.elapsed() < DEDUP_WINDOW);
+        let mut wait = Duration::from_secs(0);
+        for entry in entries.iter_mut() {
+            let sent = match recently_sent.get(&dedup_key(entry)) {
+                Some(sent) => sent,
+                None => continue,
+            };
+            match self.retry_strategy {
+                FifoRetryStrategy::Send => {
+                    repeated.insert(entry.id.clone());
+                }
+                FifoRetryStrategy::Wait => {
+                    wait = wait.max(DEDUP_WINDOW.saturating_sub(sent.elapsed()));
+                }
+                FifoRetryStrategy::NewId => {
+                    entry.message_deduplication_id = Some(Uuid::new_v4().to_string());
+                }
+            }
+        }
+        wait
+    }
+
+    // How long to hold off sending the entries so groups SQS said were over the limit get time
+    // for their messages in flight to be received and deleted
+    fn over_limit_wait(&self, entries: &[SendMessageBatchRequestEntry]) -> Duration {
+        let mut over_limit = self.over_limit.borrow_mut();
+        let now = Instant::now();
+        over_limit.retain(|_, until| *until > now);
+        entries
+            .iter()
+            .filter_map(|entry| over_limit.get(entry.message_group_id.as_ref()?))
+            .map(|until| until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+// The message and system attribute names to receive, adding whatever the options need to the
+// ones asked for
+fn attribute_names(options: &ReplayOptions) -> (Vec<String>, Vec<String>) {
+    let with = |names: &[String], needed: Vec<&String>| {
+        let mut names = names.to_vec();
+        if !names.iter().any(|name| name == "All") {
+            for name in needed {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    };
+
+    let message_attributes = options
+        .attribute_filters
+        .iter()
+        .map(|(name, _)| name)
+        .chain(options.group_id_attribute.iter())
+        .chain(options.dedup_id_attribute.iter())
+        .collect();
+    let sent_timestamp = String::from(SENT_TIMESTAMP_ATTRIBUTE);
+    let message_group_id = String::from(MESSAGE_GROUP_ID_ATTRIBUTE);
+    let mut system_attributes = Vec::new();
+    if options.sent_since.is_some() || options.sent_until.is_some() || options.preserve_timestamp {
+        system_attributes.push(&sent_timestamp);
+    }
+    if options.preserve_order {
+        system_attributes.push(&message_group_id);
+    }
+    (
+        with(&options.message_attribute_names, message_attributes),
+        with(&options.system_attribute_names, system_attributes),
+    )
+}
+
+// The deduplication ID SQS goes by, which for content-based deduplication is a SHA-256 hash of
+// the body
+fn dedup_key(entry: &SendMessageBatchRequestEntry) -> String {
+    entry
+        .message_deduplication_id
+        .clone()
+        .unwrap_or_else(|| hex::encode(Sha256::digest(entry.message_body.as_bytes())))
+}
+
+fn string_attribute(message: &Message, name: &Option<String>) -> Option<String> {
+    name.as_ref()
+        .and_then(|name| message.message_attributes.as_ref()?.get(name))
+        .and_then(|value| value.string_value.clone())
+}
+
+// Standard queues reject the FIFO-only group and deduplication IDs, and FIFO queues require a
+// group ID
+pub(crate) fn fifo_ids(
+    dest: &QueueDetails,
+    ids: FifoIds,
+    output: &Output,
+) -> Result<Option<FifoIds>, Error> {
+    if dest.fifo {
+        if ids.group_id.is_none() && ids.group_id_attribute.is_none() && !ids.source_group_id {
+            return Err(Error::MissingMessageGroupId);
+        }
+        Ok(Some(ids))
+    } else {
+        if ids.group_id.is_some() || ids.group_id_attribute.is_some() {
+            output.emit(Event::Warning {
+                message: "Ignoring message group ID for standard destination queue",
+            });
+        }
+        Ok(None)
+    }
+}
+
+// What happened to a batch's messages
+struct BatchOutcome {
+    summary: ReplaySummary,
+    // The IDs of the messages that were replayed
+    replayed_ids: Vec<String>,
+    report_rows: Vec<ReportRow>,
+}
+
+type BatchResult = Result<BatchOutcome, Error>;
+
+type BatchFuture<'a> = Pin<Box<dyn Future<Output = BatchResult> + 'a>>;
+
+// Sends a batch to each destination queue in turn and deletes whatever was sent to all of them
+// from the source queue, returning what happened to its messages. Messages that fail for one
+// destination aren't sent to the rest, so they aren't duplicated there when they're replayed again.
+async fn replay_batch(
+    source_client: &dyn Sqs,
+    dest_client: &dyn Sqs,
+    options: &ReplayOptions,
+    source_url: &str,
+    destinations: &[(&str, Option<FifoIds>)],
+    messages: Vec<Message>,
+    output: &Output,
+) -> BatchResult {
+    let messages: Vec<&Message> = messages.iter().collect();
+    let mut sent = messages.clone();
+    let mut report_rows = Vec::new();
+    let mut deduplicated = 0;
+    let mut send_requests = 0;
+    for (dest_url, fifo_ids) in destinations {
+        let attempted = sent;
+        let mut results = Vec::new();
+        let mut pending = attempted.clone();
+        let mut attempt = 0;
+        loop {
+            let batch_sent = match send_message_batch(
+                dest_client,
+                dest_url,
+                &pending,
+                fifo_ids,
+                options.delay_seconds.as_ref(),
+                &options.retry_policy,
+                output,
+            )
+            .await
+            {
+                Ok(batch_sent) => batch_sent,
+                // Some of the batch may already have been sent on an earlier attempt, so give up
+                // on the rest rather than leaving those to be sent again
+                Err(error) if options.continue_on_error || attempt > 0 => {
+                    output.emit(Event::Error {
+                        message: &error.to_string(),
+                    });
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+            deduplicated += batch_sent.deduplicated;
+            send_requests += batch_sent.requests;
+            results.extend(batch_sent.messages);
+            if options.on_partial_batch != PartialBatch::Retry
+                || batch_sent.retryable.is_empty()
+                || attempt == options.retry_policy.max_retries
+            {
+                break;
+            }
+            let delay = options.retry_policy.delay(attempt);
+            attempt += 1;
+            output.emit(Event::Warning {
+                message: &format!(
+                    "{} messages failed to send to {}, retrying them in {}ms (attempt {} of {})",
+                    batch_sent.retryable.len(),
+                    dest_url,
+                    delay.as_millis(),
+                    attempt,
+                    options.retry_policy.max_retries
+                ),
+            });
+            tokio::time::delay_for(delay).await;
+            pending = batch_sent.retryable;
+        }
+        for m in attempted.iter() {
+            let result = results.iter().find(|(sent, _)| std::ptr::eq(*sent, *m));
+            report_rows.push(ReportRow {
+                message_id: m.message_id.clone().unwrap_or_default(),
+                source_url: String::from(source_url),
+                dest_url: String::from(*dest_url),
+                sequence_number: result.and_then(|(_, sequence_number)| sequence_number.clone()),
+                status: if result.is_some() { "sent" } else { "failed" },
+            });
+        }
+        sent = results.into_iter().map(|(m, _)| m).collect();
+    }
+    let mut batch = ReplaySummary {
+        replayed: sent.len(),
+        failed: messages.len() - sent.len(),
+        deduplicated,
+        send_requests,
+        ..Default::default()
+    };
+    let delete = if options.on_partial_batch == PartialBatch::DeleteNone
+        && sent.len() < messages.len()
+    {
+        output.emit(Event::Warning {
+            message: &format!(
+                "Only {} of {} messages in the batch were sent, leaving them all in the source queue",
+                sent.len(),
+                messages.len()
+            ),
+        });
+        false
+    } else {
+        !options.copy && !sent.is_empty()
+    };
+    if delete {
+        // A failed delete is reported rather than returned, so the batch's report rows and
+        // checkpoint are still recorded. Messages left undeleted count as failed, which stops the
+        // replay unless it's continuing on errors, as they'll be received and replayed again.
+        match delete_message_batch(
+            source_client,
+            source_url,
+            &sent,
+            &options.retry_policy,
+            output,
+        )
+        .await
+        {
+            Ok(deleted) => {
+                batch.deleted = deleted.count;
+                batch.delete_requests = deleted.requests;
+                batch.expired = deleted.expired;
+                for (i, m) in sent.iter().enumerate() {
+                    if deleted.failed.contains(&i) {
+                        continue;
+                    }
+                    for row in report_rows.iter_mut() {
+                        if row.status == "sent" && Some(&row.message_id) == m.message_id.as_ref() {
+                            row.status = "moved";
+                        }
+                    }
+                }
+            }
+            Err(error) => output.emit(Event::Error {
+                message: &error.to_string(),
+            }),
+        }
+        batch.failed += sent.len() - batch.deleted;
+    }
+    Ok(BatchOutcome {
+        summary: batch,
+        replayed_ids: sent.iter().filter_map(|m| m.message_id.clone()).collect(),
+        report_rows,
+    })
+}
+
+// Adds a finished batch to the summary, and records its messages in the checkpoint and report
+fn record_batch(
+    summary: &mut ReplaySummary,
+    checkpoint: &mut Option<Checkpoint>,
+    report: &mut Option<Report>,
+    finished: Finished,
+    output: &Output,
+) -> Result<(), Error> {
+    let batch = finished.result?;
+    output.emit(Event::BatchFinished {
+        batch: finished.batch_no,
+        summary: &batch.summary,
+    });
+    summary.add(batch.summary);
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.record(batch.replayed_ids)?;
+    }
+    if let Some(report) = report {
+        report.record(batch.report_rows)?;
+    }
+    Ok(())
+}
+
+// A batch that's being sent and deleted
+struct InFlight<'a> {
+    size: usize,
+    // The number of the receive that completed it
+    batch_no: u32,
+    // The FIFO message groups it has messages from
+    groups: HashSet<String>,
+    batch: BatchFuture<'a>,
+}
+
+// The message groups the messages will be sent with to any of the FIFO destinations
+fn message_groups(
+    destinations: &[(&str, Option<FifoIds>)],
+    messages: &[Message],
+) -> HashSet<String> {
+    destinations
+        .iter()
+        .filter_map(|(_, fifo_ids)| fifo_ids.as_ref())
+        .flat_map(|fifo_ids| messages.iter().filter_map(move |m| fifo_ids.group_id(m)))
+        .collect()
+}
+
+// A batch that's finished being sent and deleted
+struct Finished {
+    size: usize,
+    batch_no: u32,
+    result: BatchResult,
+}
+
+impl Finished {
+    fn new(in_flight: InFlight<'_>, result: BatchResult) -> Self {
+        Finished {
+            size: in_flight.size,
+            batch_no: in_flight.batch_no,
+            result,
+        }
+    }
+}
+
+// Waits for whichever of the first `concurrency` batches finishes first and removes it, returning
+// its size, number and result. The batches are all polled from the replay's own task, so the counts never
+// need synchronising.
+async fn next_finished(in_flight: &mut Vec<InFlight<'_>>, concurrency: usize) -> Finished {
+    poll_fn(|cx| {
+        for i in 0..in_flight.len().min(concurrency) {
+            if let Poll::Ready(result) = in_flight[i].batch.as_mut().poll(cx) {
+                return Poll::Ready(Finished::new(in_flight.remove(i), result));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+// Waits for `request` while sending the first `concurrency` batches, moving any that finish to
+// `finished` and starting the ones queued behind them
+async fn while_sending<T>(
+    request: impl Future<Output = T>,
+    in_flight: &mut Vec<InFlight<'_>>,
+    concurrency: usize,
+    finished: &mut Vec<Finished>,
+) -> T {
+    let mut request = Box::pin(request);
+    poll_fn(|cx| {
+        let mut i = 0;
+        while i < in_flight.len().min(concurrency) {
+            match in_flight[i].batch.as_mut().poll(cx) {
+                Poll::Ready(result) => finished.push(Finished::new(in_flight.remove(i), result)),
+                Poll::Pending => i += 1,
+            }
+        }
+        request.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// What happened to a batch of sends.
+pub(crate) struct Sent<'a> {
+    /// The messages that were sent, along with their sequence numbers if the destination is a
+    /// FIFO queue
+    pub(crate) messages: Vec<(&'a Message, Option<String>)>,
+    /// Messages SQS failed to send through no fault of their own, which may be sent if tried again
+    pub(crate) retryable: Vec<&'a Message>,
+    /// How many of the sent messages may have been dropped as duplicates
+    pub(crate) deduplicated: usize,
+    /// Requests made, counting each retry
+    pub(crate) requests: usize,
+}
+
+// Sends up to 10 messages in a single request. Failed messages are reported and left for the
+// caller to leave in the source queue or try again.
+pub(crate) async fn send_message_batch<'a>(
+    client: &dyn Sqs,
+    dest_url: &str,
+    messages: &[&'a Message],
+    fifo_ids: &Option<FifoIds>,
+    delay_seconds: Option<&RangeInclusive<i64>>,
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<Sent<'a>, Error> {
+    // Entry IDs only need to be unique within the request so the index maps straight back to the message
+    let mut entries: Vec<SendMessageBatchRequestEntry> = messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| {
+            // SQS would reject the whole request, so leave these behind before it's sent. Only
+            // messages loaded from a file should ever be this big.
+            let size = message_size(m);
+            if size > MAX_MESSAGE_SIZE {
+                output.emit(Event::SendFailed {
+                    queue_url: dest_url,
+                    message_id: m.message_id.as_deref().unwrap_or("<unknown>"),
+                    code: "MessageTooLong",
+                    message: Some(&format!(
+                        "message is {} bytes, over the {} byte SQS limit",
+                        size, MAX_MESSAGE_SIZE
+                    )),
+                });
+                return None;
+            }
+            let (message_group_id, message_deduplication_id) = match fifo_ids {
+                Some(fifo_ids) => match fifo_ids.group_id(m) {
+                    Some(message_group_id) => (Some(message_group_id), fifo_ids.dedup_id(m)),
+                    None => {
+                        output.emit(Event::Warning {
+                            message: &format!(
+                                "Message ID {} has no group ID attribute, leaving it in the source queue",
+                                m.message_id.as_deref().unwrap_or("<unknown>")
+                            ),
+                        });
+                        return None;
+                    }
+                },
+                None => (None, None),
+            };
+            Some(SendMessageBatchRequestEntry {
+                id: i.to_string(),
+                message_body: match m.body.as_deref() {
+                    Some(body) if !body.is_empty() => body.to_string(),
+                    _ => String::from(EMPTY_BODY_PLACEHOLDER),
+                },
+                delay_seconds: delay_seconds
+                    .map(|range| rand::thread_rng().gen_range(range.start(), range.end() + 1)),
+                message_attributes: m.message_attributes.clone(),
+                message_deduplication_id,
+                message_group_id,
+                message_system_attributes: trace_header(m),
+            })
+        })
+        .collect();
+    if entries.is_empty() {
+        return Ok(Sent {
+            messages: Vec::new(),
+            retryable: Vec::new(),
+            deduplicated: 0,
+            requests: 0,
+        });
+    }
+    let mut repeated = HashSet::new();
+    if let Some(fifo_ids) = fifo_ids {
+        let wait = fifo_ids.check_recently_sent(&mut entries, &mut repeated);
+        if !wait.is_zero() {
+            output.emit(Event::Warning {
+                message: &format!(
+                    "Waiting {}s to send to {} so SQS doesn't drop messages sent with the same deduplication IDs less than five minutes ago",
+                    wait.as_secs_f64().ceil(),
+                    dest_url
+                ),
+            });
+            tokio::time::delay_for(wait).await;
+        }
+        let wait = fifo_ids.over_limit_wait(&entries);
+        if !wait.is_zero() {
+            output.emit(Event::Warning {
+                message: &format!(
+                    "Waiting {}ms to send to {} as message groups in the batch recently had too many messages in flight",
+                    wait.as_millis(),
+                    dest_url
+                ),
+            });
+            tokio::time::delay_for(wait).await;
+        }
+    }
+    let dedup_keys: HashMap<String, String> = entries
+        .iter()
+        .map(|entry| (entry.id.clone(), dedup_key(entry)))
+        .collect();
+
+    // SQS limits a whole request to the size of a single message, so big messages are split
+    // across as many requests as they need, in order. Messages SQS refused because their group
+    // has too many in flight are sent again after backing off, rather than failing the replay
+    // over a limit that clears as they're received.
+    let mut successful = Vec::new();
+    let mut failed = Vec::new();
+    let mut requests = 0;
+    'requests: for mut entries in split_by_size(entries) {
+        let mut attempt = 0;
+        loop {
+            let send_message_batch_input = SendMessageBatchRequest {
+                queue_url: String::from(dest_url),
+                entries: entries.clone(),
+            };
+            let result = match retry_policy
+                .run(output, || {
+                    requests += 1;
+                    client.send_message_batch(send_message_batch_input.clone())
+                })
+                .await
+            {
+                Ok(result) => result,
+                Err(error) => {
+                    let error = Error::SendMessageBatch(String::from(dest_url), Box::new(error));
+                    if successful.is_empty() {
+                        return Err(error);
+                    }
+                    // The requests before this one were sent, so those messages still need to be
+                    // deleted and the rest are left to be replayed again
+                    output.emit(Event::Error {
+                        message: &error.to_string(),
+                    });
+                    break 'requests;
+                }
+            };
+            successful.extend(result.successful);
+            let (over_limit, rejected): (Vec<_>, Vec<_>) =
+                result.failed.into_iter().partition(|entry| {
+                    entry.code == OVER_LIMIT_CODE && attempt < retry_policy.max_retries
+                });
+            failed.extend(rejected);
+            if over_limit.is_empty() {
+                break;
+            }
+
+            let delay = retry_policy.delay(attempt);
+            attempt += 1;
+            entries.retain(|entry| over_limit.iter().any(|failed| failed.id == entry.id));
+            let mut groups: Vec<&str> = entries
+                .iter()
+                .filter_map(|entry| entry.message_group_id.as_deref())
+                .collect();
+            groups.sort_unstable();
+            groups.dedup();
+            if let Some(fifo_ids) = fifo_ids {
+                let until = Instant::now() + delay;
+                let mut over_limit = fifo_ids.over_limit.borrow_mut();
+                for group in groups.iter() {
+                    over_limit.insert(group.to_string(), until);
+                }
+            }
+            output.emit(Event::Warning {
+            message: &format!(
+                "{} has too many messages in flight{}, slowing down and sending {} messages again in {}ms (attempt {} of {})",
+                dest_url,
+                if groups.is_empty() {
+                    String::new()
+                } else {
+                    format!(" in message groups {}", groups.join(", "))
+                },
+                entries.len(),
+                delay.as_millis(),
+                attempt,
+                retry_policy.max_retries
+            ),
+        });
+            tokio::time::delay_for(delay).await;
+        }
+    }
+
+    for entry in failed.iter() {
+        output.emit(Event::SendFailed {
+            queue_url: dest_url,
+            message_id: batch_message_id(messages, &entry.id),
+            code: &entry.code,
+            message: entry.message.as_deref(),
+        });
+    }
+
+    let deduplicated = successful
+        .iter()
+        .filter(|entry| repeated.contains(&entry.id))
+        .count();
+    if deduplicated > 0 {
+        output.emit(Event::Warning {
+            message: &format!(
+                "{} messages were sent to {} with deduplication IDs already sent there less than five minutes ago, so SQS may have dropped them as duplicates",
+                deduplicated, dest_url
+            ),
+        });
+    }
+    if let Some(fifo_ids) = fifo_ids {
+        let mut recently_sent = fifo_ids.recently_sent.borrow_mut();
+        let now = Instant::now();
+        for entry in successful.iter() {
+            if let Some(key) = dedup_keys.get(&entry.id) {
+                recently_sent.insert(key.clone(), now);
+            }
+        }
+    }
+    let batch_message = |id: &str| id.parse::<usize>().ok().and_then(|i| messages.get(i));
+    let retryable = failed
+        .iter()
+        .filter(|entry| !entry.sender_fault)
+        .filter_map(|entry| batch_message(&entry.id).copied())
+        .collect();
+    let sent = successful
+        .iter()
+        .filter_map(|entry| {
+            output.emit(Event::MessageSent {
+                message_id: batch_message_id(messages, &entry.id),
+                sequence_number: entry.sequence_number.as_deref(),
+            });
+            let message = batch_message(&entry.id)?;
+            Some((*message, entry.sequence_number.clone()))
+        })
+        .collect();
+    Ok(Sent {
+        messages: sent,
+        retryable,
+        deduplicated,
+        requests,
+    })
+}
+
+/// What happened to a batch of deletes.
+pub(crate) struct Deleted {
+    pub(crate) count: usize,
+    /// Messages whose visibility timeout ran out before they were deleted, so they'll be
+    /// received again
+    pub(crate) expired: usize,
+    /// Where the messages that weren't deleted are in the batch
+    pub(crate) failed: HashSet<usize>,
+    /// Requests made, counting each retry
+    pub(crate) requests: usize,
+}
+
+pub(crate) async fn delete_message_batch(
+    client: &dyn Sqs,
+    source_url: &str,
+    messages: &[&Message],
+    retry_policy: &RetryPolicy,
+    output: &Output,
+) -> Result<Deleted, Error> {
+    // As with sends, each entry ID is the message's index in the batch
+    let entries = messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| {
+            m.receipt_handle
+                .as_ref()
+                .map(|receipt_handle| DeleteMessageBatchRequestEntry {
+                    id: i.to_string(),
+                    receipt_handle: receipt_handle.to_string(),
+                })
+        })
+        .collect();
+    let delete_message_batch_input = DeleteMessageBatchRequest {
+        queue_url: String::from(source_url),
+        entries,
+    };
+
+    let mut requests = 0;
+    let result = retry_policy
+        .run(output, || {
+            requests += 1;
+            client.delete_message_batch(delete_message_batch_input.clone())
+        })
+        .await
+        .map_err(|error| Error::DeleteMessageBatch(String::from(source_url), Box::new(error)))?;
+
+    for entry in result.failed.iter() {
+        output.emit(Event::DeleteFailed {
+            message_id: batch_message_id(messages, &entry.id),
+            code: &entry.code,
+            message: entry.message.as_deref(),
+        });
+    }
+    let expired = result
+        .failed
+        .iter()
+        .filter(|entry| entry.code == RECEIPT_HANDLE_INVALID)
+        .count();
+    if expired > 0 {
+        output.emit(Event::Warning {
+            message: &format!(
+                "{} messages weren't deleted before their visibility timeout expired, so they'll be received again and may be duplicated. A longer visibility timeout avoids this.",
+                expired
+            ),
+        });
+    }
+    output.emit(Event::MessagesDeleted {
+        count: result.successful.len(),
+    });
+    Ok(Deleted {
+        count: result.successful.len(),
+        expired,
+        failed: result
+            .failed
+            .iter()
+            .filter_map(|entry| entry.id.parse().ok())
+            .collect(),
+        requests,
+    })
+}
+
+// The size SQS counts towards its limit: the body, plus each attribute's name, type and value
+fn message_size(message: &Message) -> usize {
+    message.body.as_ref().map_or(0, String::len) + attributes_size(&message.message_attributes)
+}
+
+fn entry_size(entry: &SendMessageBatchRequestEntry) -> usize {
+    entry.message_body.len() + attributes_size(&entry.message_attributes)
+}
+
+fn attributes_size(attributes: &Option<HashMap<String, MessageAttributeValue>>) -> usize {
+    attributes
+        .iter()
+        .flatten()
+        .map(|(name, value)| {
+            name.len()
+                + value.data_type.len()
+                + value.string_value.as_ref().map_or(0, String::len)
+                + value.binary_value.as_ref().map_or(0, |b| b.len())
+        })
+        .sum()
+}
+
+// Splits the entries, keeping their order, into requests that each stay within the size SQS
+// allows for a whole batch
+fn split_by_size(
+    entries: Vec<SendMessageBatchRequestEntry>,
+) -> Vec<Vec<SendMessageBatchRequestEntry>> {
+    let mut requests: Vec<Vec<SendMessageBatchRequestEntry>> = Vec::new();
+    let mut size = 0;
+    for entry in entries {
+        let entry_size = entry_size(&entry);
+        match requests.last_mut() {
+            Some(request) if size + entry_size <= MAX_MESSAGE_SIZE => {
+                size += entry_size;
+                request.push(entry);
+            }
+            _ => {
+                size = entry_size;
+                requests.push(vec![entry]);
+            }
+        }
+    }
+    requests
+}
+
+// Carries the X-Ray trace header over so tracing continues through the destination queue
+fn trace_header(message: &Message) -> Option<HashMap<String, MessageSystemAttributeValue>> {
+    let trace_header = message.attributes.as_ref()?.get(TRACE_HEADER_ATTRIBUTE)?;
+    let mut attributes = HashMap::new();
+    attributes.insert(
+        String::from(TRACE_HEADER_ATTRIBUTE),
+        MessageSystemAttributeValue {
+            data_type: String::from("String"),
+            string_value: Some(trace_header.clone()),
+            ..Default::default()
+        },
+    );
+    Some(attributes)
+}
+
+// Looks up the message ID for a batch entry ID, which is the message's index in the batch
+fn batch_message_id<'a>(messages: &[&'a Message], entry_id: &str) -> &'a str {
+    entry_id
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| messages.get(i))
+        .and_then(|m| m.message_id.as_deref())
+        .unwrap_or("<unknown>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputFormat;
+    use rusoto_core::{HttpDispatchError, RusotoError, RusotoFuture};
+    use rusoto_sqs::*;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct MockSqs {
+        batches: Mutex<VecDeque<Vec<Message>>>,
+        // Number of receives that should fail before messages are returned
+        receive_failures: Mutex<u32>,
+        receive_attempt_ids: Mutex<Vec<Option<String>>>,
+        // The queue each receive was from
+        received_from: Mutex<Vec<String>>,
+        // Bodies of messages the destination should refuse
+        failing_bodies: HashSet<String>,
+        // Bodies of messages the destination should refuse the first time they're sent
+        flaky_bodies: Mutex<HashSet<String>>,
+        // Fail the first send request with a message from any of these groups, as if the
+        // connection dropped, so it's retried
+        flaky_groups: Mutex<HashSet<String>>,
+        // Refuse the first message sent from any of these groups as being over the limit of
+        // messages in flight
+        over_limit_groups: Mutex<HashSet<String>>,
+        // Fail every send request outright, rather than individual entries
+        send_error: bool,
+        sent: Mutex<Vec<SendMessageBatchRequestEntry>>,
+        // How many entries each send request had
+        send_sizes: Mutex<Vec<usize>>,
+        // The queue each sent entry went to
+        sent_to: Mutex<Vec<String>>,
+        deleted: Mutex<Vec<String>>,
+        // Receipt handles the source should refuse to delete with
+        expired_receipt_handles: HashSet<String>,
+        content_based_deduplication: bool,
+    }
+
+    impl MockSqs {
+        fn with_batches(batches: Vec<Vec<Message>>) -> MockSqs {
+            MockSqs {
+                batches: Mutex::new(batches.into_iter().collect()),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Sqs for MockSqs {
+        fn add_permission(&self, _: AddPermissionRequest) -> RusotoFuture<(), AddPermissionError> {
+            unimplemented!()
+        }
+
+        fn change_message_visibility(
+            &self,
+            _: ChangeMessageVisibilityRequest,
+        ) -> RusotoFuture<(), ChangeMessageVisibilityError> {
+            unimplemented!()
+        }
+
+        fn change_message_visibility_batch(
+            &self,
+            _: ChangeMessageVisibilityBatchRequest,
+        ) -> RusotoFuture<ChangeMessageVisibilityBatchResult, ChangeMessageVisibilityBatchError>
+        {
+            unimplemented!()
+        }
+
+        fn create_queue(
+            &self,
+            _: CreateQueueRequest,
+        ) -> RusotoFuture<CreateQueueResult, CreateQueueError> {
+            unimplemented!()
+        }
+
+        fn delete_message(&self, _: DeleteMessageRequest) -> RusotoFuture<(), DeleteMessageError> {
+            unimplemented!()
+        }
+
+        fn delete_message_batch(
+            &self,
+            input: DeleteMessageBatchRequest,
+        ) -> RusotoFuture<DeleteMessageBatchResult, DeleteMessageBatchError> {
+            let mut result = DeleteMessageBatchResult::default();
+            for entry in input.entries {
+                if self.expired_receipt_handles.contains(&entry.receipt_handle) {
+                    result.failed.push(BatchResultErrorEntry {
+                        id: entry.id,
+                        code: String::from(RECEIPT_HANDLE_INVALID),
+                        sender_fault: true,
+                        ..Default::default()
+                    });
+                    continue;
+                }
+                self.deleted.lock().unwrap().push(entry.receipt_handle);
+                result
+                    .successful
+                    .push(DeleteMessageBatchResultEntry { id: entry.id });
+            }
+            Ok(result).into()
+        }
+
+        fn delete_queue(&self, _: DeleteQueueRequest) -> RusotoFuture<(), DeleteQueueError> {
+            unimplemented!()
+        }
+
+        fn get_queue_attributes(
+            &self,
+            input: GetQueueAttributesRequest,
+        ) -> RusotoFuture<GetQueueAttributesResult, GetQueueAttributesError> {
+            let mut attributes = HashMap::new();
+            attributes.insert(
+                String::from("FifoQueue"),
+                is_fifo_queue(&input.queue_url).to_string(),
+            );
+            attributes.insert(
+                String::from("ContentBasedDeduplication"),
+                self.content_based_deduplication.to_string(),
+            );
+            let available: usize = self.batches.lock().unwrap().iter().map(Vec::len).sum();
+            attributes.insert(
+                String::from("ApproximateNumberOfMessages"),
+                available.to_string(),
+            );
+            Ok(GetQueueAttributesResult {
+                attributes: Some(attributes),
+            })
+            .into()
+        }
+
+        fn get_queue_url(
+            &self,
+            _: GetQueueUrlRequest,
+        ) -> RusotoFuture<GetQueueUrlResult, GetQueueUrlError> {
+            unimplemented!()
+        }
+
+        fn list_dead_letter_source_queues(
+            &self,
+            _: ListDeadLetterSourceQueuesRequest,
+        ) -> RusotoFuture<ListDeadLetterSourceQueuesResult, ListDeadLetterSourceQueuesError>
+        {
+            unimplemented!()
+        }
+
+        fn list_queue_tags(
+            &self,
+            _: ListQueueTagsRequest,
+        ) -> RusotoFuture<ListQueueTagsResult, ListQueueTagsError> {
+            unimplemented!()
+        }
+
+        fn list_queues(
+            &self,
+            _: ListQueuesRequest,
+        ) -> RusotoFuture<ListQueuesResult, ListQueuesError> {
+            unimplemented!()
+        }
+
+        fn purge_queue(&self, _: PurgeQueueRequest) -> RusotoFuture<(), PurgeQueueError> {
+            unimplemented!()
+        }
+
+        fn receive_message(
+            &self,
+            input: ReceiveMessageRequest,
+        ) -> RusotoFuture<ReceiveMessageResult, ReceiveMessageError> {
+            self.receive_attempt_ids
+                .lock()
+                .unwrap()
+                .push(input.receive_request_attempt_id);
+            self.received_from.lock().unwrap().push(input.queue_url);
+            let mut receive_failures = self.receive_failures.lock().unwrap();
+            if *receive_failures > 0 {
+                *receive_failures -= 1;
+                return Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                    String::from("connection reset"),
+                )))
+                .into();
+            }
+            let messages = self.batches.lock().unwrap().pop_front();
+            Ok(ReceiveMessageResult { messages }).into()
+        }
+
+        fn remove_permission(
+            &self,
+            _: RemovePermissionRequest,
+        ) -> RusotoFuture<(), RemovePermissionError> {
+            unimplemented!()
+        }
+
+        fn send_message(
+            &self,
+            _: SendMessageRequest,
+        ) -> RusotoFuture<SendMessageResult, SendMessageError> {
+            unimplemented!()
+        }
+
+        fn send_message_batch(
+            &self,
+            input: SendMessageBatchRequest,
+        ) -> RusotoFuture<SendMessageBatchResult, SendMessageBatchError> {
+            if self.send_error
+                || input.entries.iter().map(entry_size).sum::<usize>() > MAX_MESSAGE_SIZE
+            {
+                return Err(RusotoError::Service(
+                    SendMessageBatchError::BatchRequestTooLong(String::from("too long")),
+                ))
+                .into();
+            }
+            let mut flaky_groups = self.flaky_groups.lock().unwrap();
+            let flaky = input
+                .entries
+                .iter()
+                .any(|entry| match &entry.message_group_id {
+                    Some(group_id) => flaky_groups.remove(group_id),
+                    None => false,
+                });
+            if flaky {
+                return Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                    String::from("connection reset"),
+                )))
+                .into();
+            }
+            let mut result = SendMessageBatchResult::default();
+            self.send_sizes.lock().unwrap().push(input.entries.len());
+            for entry in input.entries {
+                if self.failing_bodies.contains(&entry.message_body)
+                    || self
+                        .flaky_bodies
+                        .lock()
+                        .unwrap()
+                        .remove(&entry.message_body)
+                {
+                    result.failed.push(BatchResultErrorEntry {
+                        id: entry.id,
+                        code: String::from("InternalError"),
+                        sender_fault: false,
+                        ..Default::default()
+                    });
+                } else if entry
+                    .message_group_id
+                    .as_ref()
+                    .is_some_and(|group_id| self.over_limit_groups.lock().unwrap().remove(group_id))
+                {
+                    result.failed.push(BatchResultErrorEntry {
+                        id: entry.id,
+                        code: String::from(OVER_LIMIT_CODE),
+                        sender_fault: true,
+                        ..Default::default()
+                    });
+                } else {
+                    // FIFO queues number each message they accept
+                    result.successful.push(SendMessageBatchResultEntry {
+                        id: entry.id.clone(),
+                        sequence_number: if is_fifo_queue(&input.queue_url) {
+                            Some(format!("seq-{}", entry.message_body))
+                        } else {
+                            None
+                        },
+                        ..Default::default()
+                    });
+                    self.sent.lock().unwrap().push(entry);
+                    self.sent_to.lock().unwrap().push(input.queue_url.clone());
+                }
+            }
+            Ok(result).into()
+        }
+
+        fn set_queue_attributes(
+            &self,
+            _: SetQueueAttributesRequest,
+        ) -> RusotoFuture<(), SetQueueAttributesError> {
+            unimplemented!()
+        }
+
+        fn tag_queue(&self, _: TagQueueRequest) -> RusotoFuture<(), TagQueueError> {
+            unimplemented!()
+        }
+
+        fn untag_queue(&self, _: UntagQueueRequest) -> RusotoFuture<(), UntagQueueError> {
+            unimplemented!()
+        }
+    }
+
+    fn message(id: &str) -> Message {
+        Message {
+            message_id: Some(id.to_string()),
+            receipt_handle: Some(format!("receipt-{}", id)),
+            body: Some(format!("body-{}", id)),
+            ..Default::default()
+        }
+    }
+
+    fn options() -> ReplayOptions {
+        ReplayOptions {
+            source_urls: vec![String::from(
+                "https://sqs.eu-west-1.amazonaws.com/123456789012/source",
+            )],
+            dest_urls: vec![String::from(
+                "https://sqs.eu-west-1.amazonaws.com/123456789012/destination.fifo",
+            )],
+            message_group_id: Some(String::from("group")),
+            group_id_attribute: None,
+            dedup_id_attribute: None,
+            dedup_strategy: DedupStrategy::Random,
+            max_messages: None,
+            max_duration: None,
+            batch_size: MAX_BATCH_SIZE,
+            wait_time_seconds: 0,
+            max_empty_receives: DEFAULT_EMPTY_RECEIVES,
+            follow: false,
+            wait_for_messages: false,
+            startup_timeout: None,
+            batch_window: None,
+            visibility_timeout: 30,
+            copy: false,
+            on_partial_batch: PartialBatch::DeleteSucceeded,
+            fifo_retry_strategy: FifoRetryStrategy::Send,
+            continue_on_error: false,
+            delay_seconds: None,
+            filter: None,
+            attribute_filters: Vec::new(),
+            message_attribute_names: Vec::new(),
+            system_attribute_names: Vec::new(),
+            sent_since: None,
+            sent_until: None,
+            sample_rate: None,
+            sample_seed: None,
+            on_empty: EmptyBodies::Skip,
+            replacements: Vec::new(),
+            preserve_timestamp: false,
+            redrive: false,
+            require_fifo: None,
+            allow_same_queue: false,
+            force: false,
+            dry_run: false,
+            fail_if_empty: false,
+            concurrency: 1,
+            concurrency_per_group: false,
+            prefetch: 0,
+            preserve_order: false,
+            rate_limit: None,
+            retry_policy: RetryPolicy::default(),
+            checkpoint_file: None,
+            report_file: None,
+            approve: None,
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    fn output() -> Output {
+        Output::new(OutputFormat::Human)
+    }
+
+    #[tokio::test]
+    async fn replays_partial_batches_until_queue_is_drained() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1"), message("2"), message("3")],
+            vec![message("4")],
+            vec![],
+            vec![message("5"), message("6")],
+        ]);
+
+        replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        let sent: Vec<String> = client
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.message_body.clone())
+            .collect();
+        assert_eq!(
+            sent,
+            vec!["body-1", "body-2", "body-3", "body-4", "body-5", "body-6"]
+        );
+        assert_eq!(client.deleted.lock().unwrap().len(), 6);
+        assert!(client.batches.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replays_nothing_from_an_empty_queue() {
+        let client = MockSqs::with_batches(vec![]);
+
+        let summary = replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.received, 0);
+        assert_eq!(summary.replayed, 0);
+        assert!(client.sent.lock().unwrap().is_empty());
+        assert_eq!(
+            client.receive_attempt_ids.lock().unwrap().len() as u32,
+            DEFAULT_EMPTY_RECEIVES
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_max_empty_receives_before_finishing() {
+        let client = MockSqs::with_batches(vec![]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                max_empty_receives: 5,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(client.receive_attempt_ids.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn receives_from_each_source_in_turn_until_all_are_drained() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1")],
+            vec![message("2")],
+            vec![message("3")],
+        ]);
+        let (first, second) = ("first", "second");
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                source_urls: vec![String::from(first), String::from(second)],
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 3);
+        // The second source is drained first, after which only the first is received from
+        assert_eq!(
+            *client.received_from.lock().unwrap(),
+            vec![first, second, first, second, first, second, first]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_messages() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1"), message("2"), message("3")],
+            vec![message("4")],
+        ]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                max_messages: Some(2),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 2);
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-1", "receipt-2"]
+        );
+        assert_eq!(client.batches.lock().unwrap().len(), 1);
+        assert_eq!(summary.stop_reason, StopReason::MessageLimit);
+    }
+
+    #[tokio::test]
+    async fn stops_receiving_once_out_of_time() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                max_duration: Some(Duration::from_secs(0)),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.received, 0);
+        assert_eq!(summary.stop_reason, StopReason::TimeLimit);
+    }
+
+    #[tokio::test]
+    async fn fails_once_receives_run_out_of_retries() {
+        let client = MockSqs {
+            receive_failures: Mutex::new(2),
+            ..MockSqs::with_batches(vec![vec![message("1")]])
+        };
+
+        let result = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                retry_policy: RetryPolicy {
+                    max_retries: 1,
+                    base_delay: Duration::from_millis(0),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::ReceiveMessage(..))));
+        assert!(client.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn copy_mode_stops_once_messages_come_back_around() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1"), message("2")],
+            vec![message("3")],
+            vec![message("1"), message("3")],
+            vec![message("4")],
+        ]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                copy: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(client.sent.lock().unwrap().len(), 3);
+        assert!(client.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn preserves_message_attributes() {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            String::from("colour"),
+            MessageAttributeValue {
+                data_type: String::from("String"),
+                string_value: Some(String::from("orange")),
+                ..Default::default()
+            },
+        );
+        attributes.insert(
+            String::from("count"),
+            MessageAttributeValue {
+                data_type: String::from("Number"),
+                string_value: Some(String::from("42")),
+                ..Default::default()
+            },
+        );
+        attributes.insert(
+            String::from("checksum"),
+            MessageAttributeValue {
+                data_type: String::from("Binary.md5"),
+                binary_value: Some(vec![0xde, 0xad, 0xbe, 0xef].into()),
+                ..Default::default()
+            },
+        );
+        let client = MockSqs::with_batches(vec![vec![Message {
+            message_attributes: Some(attributes.clone()),
+            ..message("1")
+        }]]);
+
+        replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(sent[0].message_attributes, Some(attributes));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_original_sent_timestamp_when_asked_to() {
+        let sent_at = |timestamp: &str| {
+            Some(
+                vec![(
+                    String::from(SENT_TIMESTAMP_ATTRIBUTE),
+                    String::from(timestamp),
+                )]
+                .into_iter()
+                .collect(),
+            )
+        };
+        let mut replayed_before = HashMap::new();
+        replayed_before.insert(
+            String::from(ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE),
+            MessageAttributeValue {
+                data_type: String::from("Number"),
+                string_value: Some(String::from("1500000000000")),
+                ..Default::default()
+            },
+        );
+        let client = MockSqs::with_batches(vec![vec![
+            Message {
+                attributes: sent_at("1600000000000"),
+                ..message("1")
+            },
+            Message {
+                attributes: sent_at("1700000000000"),
+                message_attributes: Some(replayed_before),
+                ..message("2")
+            },
+        ]]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                preserve_timestamp: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        let original_sent_timestamp = |i: usize| {
+            sent[i].message_attributes.as_ref().unwrap()[ORIGINAL_SENT_TIMESTAMP_ATTRIBUTE]
+                .string_value
+                .clone()
+        };
+        assert_eq!(original_sent_timestamp(0).as_deref(), Some("1600000000000"));
+        assert_eq!(original_sent_timestamp(1).as_deref(), Some("1500000000000"));
+    }
+
+    #[test]
+    fn receives_the_attributes_the_options_need() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        assert_eq!(attribute_names(&options()), (Vec::new(), Vec::new()));
+        assert_eq!(
+            attribute_names(&ReplayOptions {
+                message_attribute_names: names(&["All"]),
+                system_attribute_names: names(&["All"]),
+                preserve_timestamp: true,
+                ..options()
+            }),
+            (names(&["All"]), names(&["All"]))
+        );
+        assert_eq!(
+            attribute_names(&ReplayOptions {
+                message_attribute_names: names(&["trace", "tenant"]),
+                system_attribute_names: names(&["AWSTraceHeader"]),
+                attribute_filters: vec![(String::from("tenant"), String::from("acme"))],
+                dedup_id_attribute: Some(String::from("dedup")),
+                preserve_timestamp: true,
+                preserve_order: true,
+                ..options()
+            }),
+            (
+                names(&["trace", "tenant", "dedup"]),
+                names(&["AWSTraceHeader", "SentTimestamp", "MessageGroupId"])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn propagates_the_trace_header() {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            String::from("AWSTraceHeader"),
+            String::from("Root=1-5e1b4151-5ac6c58f5b5dbd6a5d1a0d0a"),
+        );
+        attributes.insert(String::from("ApproximateReceiveCount"), String::from("3"));
+        let client = MockSqs::with_batches(vec![vec![Message {
+            attributes: Some(attributes),
+            ..message("1")
+        }]]);
+
+        replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        let system_attributes = sent[0].message_system_attributes.as_ref().unwrap();
+        assert_eq!(system_attributes.len(), 1);
+        assert_eq!(
+            system_attributes["AWSTraceHeader"].string_value.as_deref(),
+            Some("Root=1-5e1b4151-5ac6c58f5b5dbd6a5d1a0d0a")
+        );
+    }
+
+    #[tokio::test]
+    async fn omits_fifo_fields_for_standard_destinations() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dest_urls: vec![String::from(
+                    "https://sqs.eu-west-1.amazonaws.com/123456789012/standard",
+                )],
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(sent[0].message_group_id, None);
+        assert_eq!(sent[0].message_deduplication_id, None);
+    }
+
+    #[tokio::test]
+    async fn delays_each_message_by_a_random_time_in_the_range() {
+        let client =
+            MockSqs::with_batches(vec![(1..=10).map(|i| message(&i.to_string())).collect()]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dest_urls: vec![String::from(
+                    "https://sqs.eu-west-1.amazonaws.com/123456789012/standard",
+                )],
+                delay_seconds: Some(60..=120),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(sent.len(), 10);
+        assert!(sent.iter().all(|m| m
+            .delay_seconds
+            .is_some_and(|delay| (60..=120).contains(&delay))));
+    }
+
+    #[tokio::test]
+    async fn takes_group_ids_from_message_attributes() {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            String::from("tenant"),
+            MessageAttributeValue {
+                data_type: String::from("String"),
+                string_value: Some(String::from("acme")),
+                ..Default::default()
+            },
+        );
+        let client = MockSqs::with_batches(vec![vec![
+            Message {
+                message_attributes: Some(attributes),
+                ..message("1")
+            },
+            message("2"),
+        ]]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                group_id_attribute: Some(String::from("tenant")),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let group_ids: Vec<Option<String>> = client
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.message_group_id.clone())
+            .collect();
+        assert_eq!(
+            group_ids,
+            vec![Some(String::from("acme")), Some(String::from("group"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_in_receive_order_with_source_group_ids_when_preserving_order() {
+        let grouped = |id: &str, group_id: &str| {
+            let mut attributes = HashMap::new();
+            attributes.insert(
+                String::from(MESSAGE_GROUP_ID_ATTRIBUTE),
+                group_id.to_string(),
+            );
+            Message {
+                attributes: Some(attributes),
+                ..message(id)
+            }
+        };
+        let client = MockSqs::with_batches(vec![
+            vec![grouped("1", "a")],
+            vec![grouped("2", "b")],
+            vec![grouped("3", "a")],
+            vec![message("4")],
+        ]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                concurrency: 4,
+                preserve_order: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let sent: Vec<(String, Option<String>)> = client
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| (m.message_body.clone(), m.message_group_id.clone()))
+            .collect();
+        let expected = vec![
+            ("body-1", "a"),
+            ("body-2", "b"),
+            ("body-3", "a"),
+            ("body-4", "group"),
+        ];
+        assert_eq!(
+            sent,
+            expected
+                .into_iter()
+                .map(|(body, group_id)| (body.to_string(), Some(group_id.to_string())))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-1", "receipt-2", "receipt-3", "receipt-4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_deduplication_to_content_based_destinations() {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            String::from("dedup"),
+            MessageAttributeValue {
+                data_type: String::from("String"),
+                string_value: Some(String::from("dedup-1")),
+                ..Default::default()
+            },
+        );
+        let client = MockSqs {
+            content_based_deduplication: true,
+            ..MockSqs::with_batches(vec![vec![
+                Message {
+                    message_attributes: Some(attributes),
+                    ..message("1")
+                },
+                message("2"),
+            ]])
+        };
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dedup_id_attribute: Some(String::from("dedup")),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            sent[0].message_deduplication_id,
+            Some(String::from("dedup-1"))
+        );
+        assert_eq!(sent[1].message_deduplication_id, None);
+    }
+
+    #[tokio::test]
+    async fn derives_deduplication_ids_from_each_message() {
+        let dedup_id = |dedup_strategy| async move {
+            let client = MockSqs::with_batches(vec![vec![message("1")]]);
+            replay(
+                &client,
+                &client,
+                ReplayOptions {
+                    dedup_strategy,
+                    ..options()
+                },
+                &output(),
+            )
+            .await
+            .unwrap();
+            let sent = client.sent.lock().unwrap();
+            sent[0].message_deduplication_id.clone()
+        };
+
+        assert_eq!(
+            dedup_id(DedupStrategy::MessageId).await,
+            Some(String::from("1"))
+        );
+        assert_eq!(
+            dedup_id(DedupStrategy::BodyHash).await,
+            Some(String::from(
+                "85e08f281bf3d004784e18d04a1a8d0b8fa37ab50d6ea39c3f4b43145375762f"
+            ))
+        );
+    }
+
+    async fn replay_twice_by_message_id(strategy: FifoRetryStrategy) -> (ReplaySummary, MockSqs) {
+        // The same message comes back around, as if it had failed again after being replayed
+        let client = MockSqs::with_batches(vec![vec![message("1")], vec![message("1")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dedup_strategy: DedupStrategy::MessageId,
+                fifo_retry_strategy: strategy,
+                on_empty: EmptyBodies::Placeholder,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+        (summary, client)
+    }
+
+    #[tokio::test]
+    async fn counts_messages_sent_again_within_the_deduplication_window() {
+        let (summary, client) = replay_twice_by_message_id(FifoRetryStrategy::Send).await;
+
+        assert_eq!((summary.replayed, summary.deduplicated), (2, 1));
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            sent[0].message_deduplication_id,
+            sent[1].message_deduplication_id
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_messages_sent_again_within_the_deduplication_window_new_ids() {
+        let (summary, client) = replay_twice_by_message_id(FifoRetryStrategy::NewId).await;
+
+        assert_eq!((summary.replayed, summary.deduplicated), (2, 0));
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(sent[0].message_deduplication_id.as_deref(), Some("1"));
+        assert_ne!(
+            sent[0].message_deduplication_id,
+            sent[1].message_deduplication_id
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_batches_concurrently_to_standard_destinations() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1"), message("2")],
+            vec![message("3")],
+            vec![message("4"), message("5")],
+        ]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dest_urls: vec![String::from(
+                    "https://sqs.eu-west-1.amazonaws.com/123456789012/standard",
+                )],
+                concurrency: 2,
+                max_messages: Some(4),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 4);
+        assert_eq!(client.sent.lock().unwrap().len(), 4);
+        assert_eq!(client.deleted.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn sends_batches_side_by_side_but_in_order_within_each_group() {
+        let grouped = |id: &str, group: &str| {
+            let mut attributes = HashMap::new();
+            attributes.insert(
+                String::from("group"),
+                MessageAttributeValue {
+                    data_type: String::from("String"),
+                    string_value: Some(String::from(group)),
+                    ..Default::default()
+                },
+            );
+            Message {
+                message_attributes: Some(attributes),
+                ..message(id)
+            }
+        };
+        // The first batch for group a has to be retried, which the second has to wait for
+        let client = MockSqs {
+            flaky_groups: Mutex::new(vec![String::from("a")].into_iter().collect()),
+            ..MockSqs::with_batches(vec![
+                vec![grouped("1", "a")],
+                vec![grouped("2", "b")],
+                vec![grouped("3", "a")],
+            ])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                message_group_id: None,
+                group_id_attribute: Some(String::from("group")),
+                concurrency: 3,
+                concurrency_per_group: true,
+                retry_policy: RetryPolicy {
+                    max_retries: 1,
+                    base_delay: Duration::from_millis(50),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 3);
+        let sent: Vec<String> = client
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.message_body.clone())
+            .collect();
+        assert_eq!(sent, vec!["body-2", "body-1", "body-3"]);
+    }
+
+    #[tokio::test]
+    async fn backs_off_groups_over_the_in_flight_limit() {
+        let client = MockSqs {
+            over_limit_groups: Mutex::new(vec![String::from("group")].into_iter().collect()),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")], vec![message("3")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                retry_policy: RetryPolicy {
+                    max_retries: 1,
+                    base_delay: Duration::from_millis(1),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 3);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(*client.send_sizes.lock().unwrap(), vec![2, 1, 1]);
+        assert_eq!(client.deleted.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn receives_ahead_while_a_batch_is_sent() {
+        let client = MockSqs {
+            flaky_groups: Mutex::new(vec![String::from("group")].into_iter().collect()),
+            ..MockSqs::with_batches(vec![vec![message("1")], vec![message("2")], vec![]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                prefetch: 1,
+                max_empty_receives: 1,
+                retry_policy: RetryPolicy {
+                    max_retries: 1,
+                    base_delay: Duration::from_millis(50),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        // The second batch is queued behind the first while it's retried, so they still arrive in
+        // the order they were received
+        assert_eq!(summary.replayed, 2);
+        let sent: Vec<String> = client
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.message_body.clone())
+            .collect();
+        assert_eq!(sent, vec!["body-1", "body-2"]);
+    }
+
+    #[tokio::test]
+    async fn leaves_batches_received_ahead_after_a_failure() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-1")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1")], vec![message("2")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                prefetch: 2,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.stop_reason, StopReason::Failed);
+        assert_eq!(summary.replayed, 0);
+        assert!(client.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_receives_with_the_same_attempt_id() {
+        let client = MockSqs {
+            receive_failures: Mutex::new(1),
+            ..MockSqs::with_batches(vec![vec![message("1")]])
+        };
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                source_urls: vec![String::from(
+                    "https://sqs.eu-west-1.amazonaws.com/123456789012/source.fifo",
+                )],
+                retry_policy: RetryPolicy {
+                    max_retries: 1,
+                    base_delay: std::time::Duration::from_millis(0),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        let attempt_ids = client.receive_attempt_ids.lock().unwrap();
+        assert!(attempt_ids[0].is_some());
+        assert_eq!(attempt_ids[0], attempt_ids[1]);
+        assert_ne!(attempt_ids[1], attempt_ids[2]);
+        assert_eq!(client.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_replays_messages_matching_the_filter() {
+        let client = MockSqs::with_batches(vec![
+            vec![
+                Message {
+                    body: Some(String::from("{\"type\": \"order\"}")),
+                    ..message("1")
+                },
+                Message {
+                    body: Some(String::from("{\"type\": \"refund\"}")),
+                    ..message("2")
+                },
+            ],
+            vec![Message {
+                body: Some(String::from("{\"type\": \"order\"}")),
+                ..message("3")
+            }],
+        ]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                filter: Some(Regex::new(r#""type": "order""#).unwrap()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 2);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-1", "receipt-3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_replays_messages_sent_in_the_time_window() {
+        let sent_at = |id: &str, sent_timestamp: Option<&str>| Message {
+            attributes: sent_timestamp.map(|sent_timestamp| {
+                vec![(
+                    String::from(SENT_TIMESTAMP_ATTRIBUTE),
+                    String::from(sent_timestamp),
+                )]
+                .into_iter()
+                .collect()
+            }),
+            ..message(id)
+        };
+        let client = MockSqs::with_batches(vec![vec![
+            // 2024-05-01T08:59:59.999Z, just before the window
+            sent_at("1", Some("1714553999999")),
+            // 2024-05-01T09:00:00Z, when it opens
+            sent_at("2", Some("1714554000000")),
+            // 2024-05-01T09:59:59Z
+            sent_at("3", Some("1714557599000")),
+            // 2024-05-01T10:00:00Z, when it closes
+            sent_at("4", Some("1714557600000")),
+            sent_at("5", None),
+        ]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                sent_since: Some("2024-05-01T09:00:00Z".parse().unwrap()),
+                sent_until: Some("2024-05-01T10:00:00Z".parse().unwrap()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((summary.replayed, summary.skipped), (2, 3));
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-2", "receipt-3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_nothing_when_no_messages_match_the_filter() {
+        let client = MockSqs::with_batches(vec![vec![message("1"), message("2")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                filter: Some(Regex::new("^order").unwrap()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 0);
+        assert_eq!(summary.skipped, 2);
+        assert!(client.sent.lock().unwrap().is_empty());
+        assert!(client.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_replays_messages_with_every_filtered_attribute() {
+        let attribute = |value: &str| MessageAttributeValue {
+            data_type: String::from("String"),
+            string_value: Some(value.to_string()),
+            ..Default::default()
+        };
+        let mut both = HashMap::new();
+        both.insert(String::from("tenant"), attribute("acme"));
+        both.insert(String::from("region"), attribute("eu"));
+        let mut one = HashMap::new();
+        one.insert(String::from("tenant"), attribute("acme"));
+        let client = MockSqs::with_batches(vec![vec![
+            Message {
+                message_attributes: Some(both),
+                ..message("1")
+            },
+            Message {
+                message_attributes: Some(one),
+                ..message("2")
+            },
+            message("3"),
+        ]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                attribute_filters: vec![
+                    (String::from("tenant"), String::from("acme")),
+                    (String::from("region"), String::from("eu")),
+                ],
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-1"]);
+    }
+
+    #[tokio::test]
+    async fn stops_receiving_once_shutdown_is_requested() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+        let shutdown = Shutdown::default();
+        shutdown.request();
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                shutdown,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.received, 0);
+        assert!(client.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rate_limiter_spaces_out_batches() {
+        let mut rate_limiter = RateLimiter::new(5.0);
+        let started = rate_limiter.started;
+
+        assert_eq!(rate_limiter.delay(started), Duration::from_secs(0));
+        rate_limiter.sent = 10;
+        assert_eq!(rate_limiter.delay(started), Duration::from_secs(2));
+        assert_eq!(
+            rate_limiter.delay(started + Duration::from_millis(1500)),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            rate_limiter.delay(started + Duration::from_secs(3)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_with_the_destination_client() {
+        let source = MockSqs::with_batches(vec![vec![message("1")]]);
+        let destination = MockSqs::default();
+
+        replay(&source, &destination, options(), &output())
+            .await
+            .unwrap();
+
+        assert!(source.sent.lock().unwrap().is_empty());
+        assert_eq!(destination.sent.lock().unwrap().len(), 1);
+        assert_eq!(*source.deleted.lock().unwrap(), vec!["receipt-1"]);
+    }
+
+    #[tokio::test]
+    async fn only_deletes_messages_sent_to_every_destination() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")]])
+        };
+        let dest_urls = vec![
+            String::from("https://sqs.eu-west-1.amazonaws.com/123456789012/first"),
+            String::from("https://sqs.eu-west-1.amazonaws.com/123456789012/second"),
+        ];
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dest_urls: dest_urls.clone(),
+                message_group_id: None,
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(*client.sent_to.lock().unwrap(), dest_urls);
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-1"]);
+    }
+
+    // Receipt handles of the messages deleted after replaying a sample of 1000 messages
+    async fn replayed_by_sample(sample_rate: f64, sample_seed: u64) -> Vec<String> {
+        let batches = (0..100)
+            .map(|batch| {
+                (0..10)
+                    .map(|i| message(&(batch * 10 + i).to_string()))
+                    .collect()
+            })
+            .collect();
+        let client = MockSqs::with_batches(batches);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                sample_rate: Some(sample_rate),
+                sample_seed: Some(sample_seed),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed + summary.skipped, 1000);
+        let deleted = client.deleted.lock().unwrap().clone();
+        assert_eq!(deleted.len(), summary.replayed);
+        deleted
+    }
+
+    #[tokio::test]
+    async fn replays_roughly_the_sample_rate() {
+        let replayed = replayed_by_sample(0.1, 42).await.len();
+        assert!((50..=150).contains(&replayed), "replayed {}", replayed);
+
+        let replayed = replayed_by_sample(0.75, 7).await.len();
+        assert!((700..=800).contains(&replayed), "replayed {}", replayed);
+    }
+
+    #[tokio::test]
+    async fn samples_the_same_messages_with_the_same_seed() {
+        assert_eq!(
+            replayed_by_sample(0.5, 1).await,
+            replayed_by_sample(0.5, 1).await
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_before_receiving_without_a_group_id_for_a_fifo_destination() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        let result = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                message_group_id: None,
+                ..options()
+            },
+            &output(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::MissingMessageGroupId)));
+        assert!(client.receive_attempt_ids.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn handles_empty_bodies() {
+        let empty = || Message {
+            body: Some(String::new()),
+            ..message("2")
+        };
+        let replay_with = |on_empty| async move {
+            let client = MockSqs::with_batches(vec![vec![message("1"), empty(), message("3")]]);
+            let summary = replay(
+                &client,
+                &client,
+                ReplayOptions {
+                    on_empty,
+                    ..options()
+                },
+                &output(),
+            )
+            .await
+            .unwrap();
+            let sent: Vec<String> = client
+                .sent
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|m| m.message_body.clone())
+                .collect();
+            (summary, sent)
+        };
+
+        let (summary, sent) = replay_with(EmptyBodies::Skip).await;
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(sent, vec!["body-1", "body-3"]);
+
+        let (summary, sent) = replay_with(EmptyBodies::Placeholder).await;
+        assert_eq!(summary.replayed, 3);
+        assert_eq!(sent, vec!["body-1", "<empty>", "body-3"]);
+
+        let (summary, sent) = replay_with(EmptyBodies::Fail).await;
+        assert_eq!(summary.failed, 1);
+        assert_eq!(sent, vec!["body-1"]);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_replay_into_the_wrong_type_of_queue() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        let result = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                require_fifo: Some(false),
+                ..options()
+            },
+            &output(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::WrongQueueType(_, false))));
+        assert!(client.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_deletes_when_every_message_has_somewhere_to_go() {
+        let source = "https://sqs.eu-west-1.amazonaws.com/123456789012/source";
+        let check = |options: ReplayOptions| check_safe_to_delete(&options);
+
+        assert!(check(options()).is_ok());
+        assert!(matches!(
+            check(ReplayOptions {
+                dest_urls: Vec::new(),
+                ..options()
+            }),
+            Err(Error::UnsafeDelete(_))
+        ));
+        assert!(matches!(
+            check(ReplayOptions {
+                dest_urls: vec![String::from(" ")],
+                ..options()
+            }),
+            Err(Error::UnsafeDelete(_))
+        ));
+        // Nothing's deleted so there's nothing to lose
+        assert!(check(ReplayOptions {
+            dest_urls: Vec::new(),
+            copy: true,
+            ..options()
+        })
+        .is_ok());
+        assert!(check(ReplayOptions {
+            dest_urls: Vec::new(),
+            dry_run: true,
+            ..options()
+        })
+        .is_ok());
+        assert!(check(ReplayOptions {
+            dest_urls: Vec::new(),
+            force: true,
+            ..options()
+        })
+        .is_ok());
+
+        for dest_url in [
+            "https://sqs.eu-west-1.amazonaws.com/123456789012/source/",
+            "http://SQS.eu-west-1.amazonaws.com/123456789012/source",
+            "https://eu-west-1.queue.amazonaws.com/123456789012/source",
+        ] {
+            let same_queue = || ReplayOptions {
+                dest_urls: vec![String::from(dest_url)],
+                ..options()
+            };
+            assert!(
+                matches!(check(same_queue()), Err(Error::SameSourceAndDestination)),
+                "{}",
+                dest_url
+            );
+            assert!(check(ReplayOptions {
+                copy: true,
+                ..same_queue()
+            })
+            .is_err());
+            assert!(check(ReplayOptions {
+                allow_same_queue: true,
+                ..same_queue()
+            })
+            .is_ok());
+        }
+        assert!(!is_same_queue(
+            source,
+            "https://sqs.eu-west-2.amazonaws.com/123456789012/source"
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_replay_a_queue_into_itself() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        let result = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                dest_urls: vec![format!("{}/", options().source_urls[0])],
+                ..options()
+            },
+            &output(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::SameSourceAndDestination)));
+        assert!(client.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_if_asked_to_when_the_source_is_empty() {
+        let fail_if_empty = || ReplayOptions {
+            fail_if_empty: true,
+            ..options()
+        };
+
+        let client = MockSqs::with_batches(Vec::new());
+        let result = replay(&client, &client, fail_if_empty(), &output()).await;
+        assert!(matches!(result, Err(Error::NothingToReplay)));
+        assert!(replay(&client, &client, options(), &output()).await.is_ok());
+
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+        let summary = replay(&client, &client, fail_if_empty(), &output())
+            .await
+            .unwrap();
+        assert_eq!(summary.replayed, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_messages_when_they_come_back_around() {
+        let client = MockSqs {
+            flaky_bodies: Mutex::new(vec![String::from("body-1")].into_iter().collect()),
+            ..MockSqs::with_batches(vec![vec![message("1")], vec![message("1")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-1"]);
+    }
+
+    #[tokio::test]
+    async fn splits_batches_too_big_to_send_in_one_request() {
+        let large = |id| Message {
+            body: Some(format!("{}{}", id, "x".repeat(120_000))),
+            ..message(id)
+        };
+        let client = MockSqs::with_batches(vec![vec![large("1"), large("2"), large("3")]]);
+
+        let summary = replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.replayed, 3);
+        assert_eq!(summary.send_requests, 2);
+        assert_eq!(*client.send_sizes.lock().unwrap(), vec![2, 1]);
+        assert_eq!(client.deleted.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_receiving_after_a_failed_send() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")], vec![message("3")]])
+        };
+
+        let summary = replay(&client, &client, options(), &output())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.failed, 1);
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-1"]);
+        assert_eq!(client.batches.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_deletes_messages_that_were_sent() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2"), message("3")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            summary,
+            ReplaySummary {
+                duration: summary.duration,
+                stop_reason: StopReason::Drained,
+                received: 3,
+                replayed: 2,
+                deleted: 2,
+                skipped: 0,
+                failed: 1,
+                expired: 0,
+                deduplicated: 0,
+                receive_requests: 3,
+                send_requests: 1,
+                delete_requests: 1,
+            }
+        );
+
+        assert_eq!(client.sent.lock().unwrap().len(), 2);
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-1", "receipt-3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_the_whole_batch_when_deleting_none_after_a_partial_send() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")], vec![message("3")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                batch_size: 2,
+                on_partial_batch: PartialBatch::DeleteNone,
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((summary.replayed, summary.deleted), (2, 1));
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-3"]);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_sends_before_deleting_what_was_sent() {
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-3")].into_iter().collect(),
+            flaky_bodies: Mutex::new(vec![String::from("body-2")].into_iter().collect()),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2"), message("3")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                on_partial_batch: PartialBatch::Retry,
+                continue_on_error: true,
+                retry_policy: RetryPolicy {
+                    max_retries: 2,
+                    base_delay: Duration::from_millis(1),
+                    timeout: None,
+                },
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((summary.replayed, summary.deleted), (2, 2));
+        assert_eq!(summary.failed, 1);
+        // The first attempt sends all three, then the one that keeps failing is tried twice more
+        assert_eq!(*client.send_sizes.lock().unwrap(), vec![3, 2, 1]);
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-1", "receipt-2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_messages_too_large_for_sqs_in_the_source_queue() {
+        let client = MockSqs::with_batches(vec![vec![
+            message("1"),
+            Message {
+                body: Some("x".repeat(MAX_MESSAGE_SIZE + 1)),
+                ..message("2")
+            },
+        ]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(*client.deleted.lock().unwrap(), vec!["receipt-1"]);
+    }
+
+    #[tokio::test]
+    async fn counts_messages_whose_receipt_handles_expired() {
+        let client = MockSqs {
+            expired_receipt_handles: vec![String::from("receipt-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 2);
+        assert_eq!(summary.deleted, 1);
+        assert_eq!(summary.expired, 1);
+        assert_eq!(summary.failed, 1);
+    }
+
+    #[tokio::test]
+    async fn skips_messages_already_in_the_checkpoint() {
+        let path = std::env::temp_dir().join(format!("sqs-replay-{}.checkpoint", Uuid::new_v4()));
+        std::fs::write(&path, "1\n").unwrap();
+        let client = MockSqs::with_batches(vec![vec![message("1"), message("2")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                copy: true,
+                checkpoint_file: Some(path.clone()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+        let checkpoint = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(client.sent.lock().unwrap()[0].message_body, "body-2");
+        assert_eq!(checkpoint, "1\n2\n");
+    }
+
+    #[tokio::test]
+    async fn reports_whether_each_message_was_moved() {
+        let path = std::env::temp_dir().join(format!("sqs-replay-{}.csv", Uuid::new_v4()));
+        let client = MockSqs {
+            failing_bodies: vec![String::from("body-2")].into_iter().collect(),
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2"), message("3")]])
+        };
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                report_file: Some(path.clone()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split(',').take(5).collect())
+            .collect();
+        let source = "https://sqs.eu-west-1.amazonaws.com/123456789012/source";
+        let destination = "https://sqs.eu-west-1.amazonaws.com/123456789012/destination.fifo";
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    "message_id",
+                    "source_queue_url",
+                    "destination_queue_url",
+                    "sequence_number",
+                    "status"
+                ],
+                // Sequence numbers stay with their own messages around the one that failed
+                vec!["1", source, destination, "seq-body-1", "moved"],
+                vec!["2", source, destination, "", "failed"],
+                vec!["3", source, destination, "seq-body-3", "moved"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_bodies_with_each_replacement_applied_in_turn() {
+        let client = MockSqs::with_batches(vec![vec![message("1")]]);
+
+        replay(
+            &client,
+            &client,
+            ReplayOptions {
+                replacements: vec![
+                    (String::from("body"), String::from("message")),
+                    (String::from("-"), String::from(" ")),
+                ],
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(client.sent.lock().unwrap()[0].message_body, "message 1");
+    }
+
+    #[tokio::test]
+    async fn buffers_messages_into_full_batches_during_the_batch_window() {
+        let client = MockSqs::with_batches(vec![
+            (1..=3).map(|i| message(&i.to_string())).collect(),
+            (4..=8).map(|i| message(&i.to_string())).collect(),
+            (9..=12).map(|i| message(&i.to_string())).collect(),
+        ]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                batch_window: Some(Duration::from_secs(60)),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 12);
+        assert_eq!(*client.send_sizes.lock().unwrap(), vec![10, 2]);
+        assert_eq!(client.deleted.lock().unwrap().len(), 12);
+    }
+
+    #[tokio::test]
+    async fn stops_asking_for_approval_once_all_batches_are_approved() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1")],
+            vec![message("2")],
+            vec![message("3")],
+        ]);
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let approve_asked = asked.clone();
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                approve: Some(Box::new(move |messages: &[Message]| {
+                    let message_id = messages[0].message_id.clone().unwrap();
+                    let approval = if message_id == "1" {
+                        Approval::No
+                    } else {
+                        Approval::All
+                    };
+                    approve_asked.lock().unwrap().push(message_id);
+                    approval
+                })),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 2);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(*asked.lock().unwrap(), vec!["1", "2"]);
+        assert_eq!(
+            *client.deleted.lock().unwrap(),
+            vec!["receipt-2", "receipt-3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_first_message_before_counting_empty_receives() {
+        let client = MockSqs::with_batches(vec![vec![], vec![], vec![], vec![message("1")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                wait_for_messages: true,
+                max_empty_receives: 2,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(summary.stop_reason, StopReason::Drained);
+    }
+
+    #[tokio::test]
+    async fn counts_messages_left_behind_each_time_they_come_back() {
+        let client =
+            MockSqs::with_batches(vec![vec![message("1")], vec![message("1"), message("2")]]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                follow: true,
+                max_messages: Some(1),
+                filter: Some(Regex::new("body-2").unwrap()),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(summary.replayed, 1);
+        assert_eq!(client.sent.lock().unwrap()[0].message_body, "body-2");
+    }
+
+    #[tokio::test]
+    async fn keeps_receiving_through_empty_batches_when_following() {
+        let client = MockSqs::with_batches(vec![
+            vec![message("1")],
+            vec![],
+            vec![],
+            vec![],
+            vec![message("2")],
+        ]);
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                follow: true,
+                max_messages: Some(2),
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 2);
+        assert_eq!(summary.stop_reason, StopReason::MessageLimit);
+    }
+
+    #[tokio::test]
+    async fn never_deletes_messages_when_the_send_request_fails() {
+        let client = MockSqs {
+            send_error: true,
+            ..MockSqs::with_batches(vec![vec![message("1"), message("2")], vec![message("3")]])
+        };
+
+        let summary = replay(
+            &client,
+            &client,
+            ReplayOptions {
+                continue_on_error: true,
+                ..options()
+            },
+            &output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.replayed, 0);
+        assert_eq!(summary.failed, 3);
+        assert!(client.deleted.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::Error;
+use chrono::{SecondsFormat, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const HEADER: &str =
+    "message_id,source_queue_url,destination_queue_url,sequence_number,status,timestamp";
+
+/// What happened to a message at one of the destinations it was sent to.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ReportRow {
+    pub(crate) message_id: String,
+    pub(crate) source_url: String,
+    pub(crate) dest_url: String,
+    /// Only set for FIFO destinations
+    pub(crate) sequence_number: Option<String>,
+    /// `moved` once it's also deleted from the source, `sent` if it was left there, or `failed`
+    pub(crate) status: &'static str,
+}
+
+/// A CSV audit log with a row for every message sent or that failed to send, appended to as each
+/// batch finishes so it covers everything up to a crash.
+pub(crate) struct Report {
+    path: String,
+    file: BufWriter<File>,
+}
+
+impl Report {
+    /// Appends to the file, starting it with a header if it's new or empty.
+    pub(crate) fn open(path: &Path) -> Result<Report, Error> {
+        let display = path.display().to_string();
+        let io_error = |error| Error::Io(display.clone(), error);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        let is_empty = file.metadata().map_err(io_error)?.len() == 0;
+
+        let mut report = Report {
+            path: display,
+            file: BufWriter::new(file),
+        };
+        if is_empty {
+            writeln!(report.file, "{}", HEADER).map_err(|error| report.io_error(error))?;
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn record(&mut self, rows: Vec<ReportRow>) -> Result<(), Error> {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        for row in rows {
+            let line = [
+                row.message_id.as_str(),
+                &row.source_url,
+                &row.dest_url,
+                row.sequence_number.as_deref().unwrap_or_default(),
+                row.status,
+                &timestamp,
+            ]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",");
+            writeln!(self.file, "{}", line).map_err(|error| self.io_error(error))?;
+        }
+        self.file.flush().map_err(|error| self.io_error(error))
+    }
+
+    fn io_error(&self, error: std::io::Error) -> Error {
+        Error::Io(self.path.clone(), error)
+    }
+}
+
+// Quotes the field if it has anything in it that would break the row up
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_fields_that_would_break_the_row() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
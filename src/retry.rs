@@ -0,0 +1,202 @@
+use crate::compat::Future01Ext;
+use crate::error::Cause;
+use crate::output::{Event, Output};
+use rusoto_core::{HttpDispatchError, RusotoError, RusotoFuture};
+use std::error::Error as StdError;
+use std::fmt::Debug;
+use std::time::Duration;
+
+// Error codes SQS uses when it's shedding load rather than rejecting the request outright
+const THROTTLING_CODES: &[&str] = &["RequestThrottled", "ThrottlingException", "Throttling"];
+
+const MAX_DELAY: Duration = Duration::from_secs(20);
+
+/// How many times to retry a failed SQS request, and how long to wait between attempts.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubling on each subsequent attempt
+    pub base_delay: Duration,
+    /// How long to wait for each attempt before giving up on it and retrying
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            timeout: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends the request built by `request`, retrying transient failures with exponential backoff.
+    pub(crate) async fn run<T, E, F>(
+        &self,
+        output: &Output,
+        mut request: F,
+    ) -> Result<T, RusotoError<E>>
+    where
+        T: Send + 'static,
+        E: StdError + Debug + Send + 'static,
+        F: FnMut() -> RusotoFuture<T, E>,
+    {
+        let mut attempt = 0;
+        loop {
+            // A request that times out is treated like any other dropped connection
+            let result = match self.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, request().compat()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(RusotoError::HttpDispatch(HttpDispatchError::new(format!(
+                        "Request timed out after {}s",
+                        timeout.as_secs()
+                    )))),
+                },
+                None => request().compat().await,
+            };
+            match result {
+                Err(error) if attempt < self.max_retries && is_retryable(&error) => {
+                    let delay = self.delay(attempt);
+                    attempt += 1;
+                    output.emit(Event::Warning {
+                        message: &format!(
+                            "Request failed, retrying in {}ms (attempt {} of {}): {}",
+                            delay.as_millis(),
+                            attempt,
+                            self.max_retries,
+                            Cause(&error)
+                        ),
+                    });
+                    tokio::time::delay_for(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The same policy with longer timeouts, for requests like long polling receives that are
+    /// expected to take a while.
+    pub(crate) fn allowing(&self, extra: Duration) -> RetryPolicy {
+        RetryPolicy {
+            timeout: self.timeout.map(|timeout| timeout + extra),
+            ..self.clone()
+        }
+    }
+
+    /// The longest a request can spend waiting between retries before it gives up.
+    pub(crate) fn total_delay(&self) -> Duration {
+        (0..self.max_retries)
+            .map(|attempt| self.delay(attempt))
+            .sum()
+    }
+
+    /// How long to wait before retrying after `attempt` retries.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+}
+
+// Network problems, server errors and throttling are worth retrying. Anything SQS understood and
+// rejected, like a queue that doesn't exist or bad credentials, will fail the same way again.
+fn is_retryable<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => {
+            response.status.is_server_error()
+                || THROTTLING_CODES
+                    .iter()
+                    .any(|code| response.body_as_str().contains(code))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputFormat;
+    use rusoto_core::HttpDispatchError;
+    use rusoto_sqs::ReceiveMessageError;
+    use std::cell::Cell;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(0),
+            timeout: None,
+        }
+    }
+
+    fn dispatch_error() -> RusotoError<ReceiveMessageError> {
+        RusotoError::HttpDispatch(HttpDispatchError::new(String::from("connection reset")))
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let attempts = Cell::new(0);
+
+        let result = policy()
+            .run(&Output::new(OutputFormat::Human), || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() < 3 {
+                    Err(dispatch_error()).into()
+                } else {
+                    Ok(()).into()
+                }
+            })
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = policy()
+            .run(&Output::new(OutputFormat::Human), || {
+                attempts.set(attempts.get() + 1);
+                Err(dispatch_error()).into()
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_errors_sqs_rejected() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = policy()
+            .run(&Output::new(OutputFormat::Human), || {
+                attempts.set(attempts.get() + 1);
+                Err(RusotoError::Service(ReceiveMessageError::OverLimit(
+                    String::from("too many messages in flight"),
+                )))
+                .into()
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_a_limit() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            timeout: None,
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+        assert_eq!(policy.delay(40), MAX_DELAY);
+    }
+}
@@ -0,0 +1,39 @@
+use log::warn;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The usual exit code for a process killed by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Set once the user has asked to stop, so long running commands can finish what they're in the
+/// middle of rather than leaving messages received but not deleted.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Requests a shutdown on the first Ctrl-C, and exits straight away on the second.
+    pub fn on_ctrl_c() -> Shutdown {
+        let shutdown = Shutdown::default();
+        let requested = shutdown.requested.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if requested.swap(true, Ordering::SeqCst) {
+                    process::exit(INTERRUPTED_EXIT_CODE);
+                }
+                warn!("Finishing batches in flight before exiting, press Ctrl-C again to exit now");
+            }
+        });
+        shutdown
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}